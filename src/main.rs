@@ -17,37 +17,53 @@ pub(crate) mod routes;
 pub(crate) mod tools;
 
 use routes::login::login_handler;
-use routes::callback::callback_handler;
+use routes::callback::{callback_handler, callback_post_handler};
 use routes::sessionstatus::sessionstatus_handler;
 use routes::details::user_details_handler;
 use routes::logout::logout_handler;
+use routes::readyz::readyz_handler;
+use routes::httpsredirect::https_redirect_handler;
+use routes::stepup::stepup_handler;
+use routes::events::events_handler;
+use routes::token::token_handler;
 use crate::tools::settings::{new_logger_settings, MainConfiguration};
+use crate::tools::provider::{spawn_discovery_task, spawn_refresh_task, ProviderClient};
+use crate::tools::startup::with_backoff;
+use crate::tools::lifecycle::{spawn_signal_task, track_in_flight, Lifecycle};
+use crate::tools::tls::new_server_config;
+use crate::tools::clientauth::ClientAuthentication;
+use crate::tools::dpop::init_key_sealing;
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::jar::RequestObjectSigner;
+use crate::tools::requestid::{assign_request_id, REQUEST_ID_HEADER};
+use crate::tools::audit::spawn_audit_task;
+use crate::tools::events::spawn_event_tasks;
+use crate::tools::sessioninfo::{track_session_expiry, SESSION_TTL_DAYS};
+use crate::tools::telemetry::{init_telemetry, trace_request, traced, TracedSessionStore};
 use better_logger::logger;
 use std::sync::{Arc, Mutex};
 use std::process::exit;
 use actix_web::{HttpServer, App, web};
 use actix_web::web::Data;
-use actix_web::middleware::DefaultHeaders;
+use actix_web::middleware::{DefaultHeaders, from_fn};
 use actix_web::cookie::{SameSite, Key};
-use actix_web::http::header::{AUTHORIZATION, ACCEPT, CONTENT_TYPE};
+use actix_web::http::header::{HeaderName, AUTHORIZATION, ACCEPT, CONTENT_TYPE};
 use actix_session::SessionMiddleware;
 use actix_session::storage::RedisSessionStore;
 use actix_session::config::PersistentSession;
 use actix_cors::Cors;
-use openidconnect::{IssuerUrl, ClientId, ClientSecret, RedirectUrl};
-use openidconnect::core::{CoreProviderMetadata, CoreClient};
-use openidconnect::reqwest::ClientBuilder;
-use openidconnect::reqwest::redirect::Policy;
 use hex::FromHex;
 use redis::Client;
 use tokio::sync::Notify;
+use opentelemetry::trace::SpanKind;
 use tokio::time::{interval, MissedTickBehavior};
 use std::time::Duration as timeDuration;
 use actix_web::cookie::time::Duration as cookieTimeDuration;
 use tokio::time::Duration as tokioDuration;
 
-pub(crate) type OpenidClientData = actix_web::web::Data<
-    openidconnect::Client<openidconnect::EmptyAdditionalClaims, 
+pub(crate) type OpenidClientData = actix_web::web::Data<ProviderClient>;
+
+pub(crate) type OpenidClient = openidconnect::Client<openidconnect::EmptyAdditionalClaims, 
     openidconnect::core::CoreAuthDisplay, 
     openidconnect::core::CoreGenderClaim, 
     openidconnect::core::CoreJweContentEncryptionAlgorithm, 
@@ -69,8 +85,7 @@ pub(crate) type OpenidClientData = actix_web::web::Data<
     openidconnect::EndpointNotSet, 
     openidconnect::EndpointNotSet, 
     openidconnect::EndpointMaybeSet, 
-    openidconnect::EndpointMaybeSet>
->;
+    openidconnect::EndpointMaybeSet>;
 
 #[actix_web::main]
 async fn main() {
@@ -90,7 +105,15 @@ async fn main() {
     let configuration_settings = match MainConfiguration::new() {
         Ok(settings) => settings,
         Err(error) => {
-            logger::error!("{:?}", error);
+            log_final("error", &format!("{:?}", error));
+            exit(1);
+        }
+    };
+
+    let tracer_provider = match init_telemetry(&configuration_settings) {
+        Ok(provider) => provider,
+        Err(error) => {
+            log_final("error", &format!("{:?}", error));
             exit(1);
         }
     };
@@ -134,16 +157,33 @@ async fn main() {
     );
     logger::info!("{}", starting_log_message);
 
-    match init(configuration_settings).await {
+    let lifecycle = Arc::new(Lifecycle::default());
+
+    let exit_code = match init(configuration_settings, lifecycle.clone()).await {
         Ok(_) => {
             shutdown.notify_waiters();
-            let log_message_1 = format!("\n{} - {}: {}\n{}", 
-                machine_name_1, 
-                container_name_1,
-                "EXITED WITH CONDITION: \"Ok()\"",
-                "If this was not planned, is an error"
-            );
-            logger::warn!("{}", log_message_1);
+            if let Some(report) = lifecycle.report() { // Planned shutdown, a signal was received
+                let log_message_3 = format!("\n{} - {}: {}\nsignal={} in_flight_at_signal={} drained={} aborted={}", 
+                    machine_name_1, 
+                    container_name_1,
+                    "SHUTDOWN COMPLETE",
+                    report.signal_name,
+                    report.in_flight_at_signal,
+                    report.in_flight_at_signal.saturating_sub(report.aborted),
+                    report.aborted
+                );
+                log_final("info", &log_message_3);
+            }
+            else {
+                let log_message_1 = format!("\n{} - {}: {}\n{}", 
+                    machine_name_1, 
+                    container_name_1,
+                    "EXITED WITH CONDITION: \"Ok()\"",
+                    "If this was not planned, is an error"
+                );
+                log_final("warn", &log_message_1);
+            }
+            0
         }
         Err(error) => {
             shutdown.notify_waiters();
@@ -154,59 +194,83 @@ async fn main() {
                 "ERROR:",
                 error
             );
-            logger::error!("{}", log_message_2);
+            log_final("error", &log_message_2);
+            1 // Startup failures (config, discovery, Redis, bind, signal handlers) must fail the container
         }
+    };
+
+    // Export the spans that are still buffered
+    if let Some(provider) = tracer_provider {
+        if let Err(error) = provider.shutdown() {
+            logger::warn!("(telemetry) shutdown failed: {:?}", error);
+        }
+    }
+
+    if exit_code != 0 {
+        exit(exit_code);
     }
 }
 
-pub(crate) async fn init(config_settings: MainConfiguration) -> Result<(), String> {
-    let config_settings_data = Data::new(config_settings);
+// The last message before the process exits is written synchronously, async logging would lose it with the runtime
+fn log_final(level: &str, message: &str) {
+    better_logger::log_sync(level, module_path!(), message);
+}
 
-    let openid_client_data = {
-        let issuer_url = match IssuerUrl::new(config_settings_data.issuer_url.as_str().to_string()) {
-            Ok(url) => url,
-            Err(error) => return Err(format!("{:?}", error)),
-        };
+pub(crate) async fn init(config_settings: MainConfiguration, lifecycle: Arc<Lifecycle>) -> Result<(), String> {
+    let config_settings_data = Data::new(config_settings);
+    let lifecycle_data = Data::from(lifecycle.clone());
 
-        let http_client = match ClientBuilder::new().redirect(Policy::none()).timeout(timeDuration::from_secs(10)).build() {
-            Ok(http_client) => http_client,
-            Err(error) => return Err(format!("{:?}", error)),
-        };
+    // Audit events go to their own sink, not to the logs
+    spawn_audit_task(&config_settings_data)?;
 
-        let provider_metadata = match CoreProviderMetadata::discover_async(issuer_url, &http_client).await {
-            Ok(data) => data,
-            Err(error) => return Err(format!("{:?}", error)),
-        };
+    // Shared by discovery and every handler that calls the provider
+    let provider_http_data = Data::new(ProviderHttp::new(&config_settings_data)?);
 
-        let redirect_url = match RedirectUrl::new(format!("{}/callback", config_settings_data.this_server_url)) {
-            Ok(url) => url,
-            Err(error) => return Err(format!("{:?}", error)),
-        };
+    // With lazy_discovery the server starts right away, /readyz reports when the provider is ready
+    let openid_client_data = {
+        let client_auth = ClientAuthentication::new(&config_settings_data)?;
+        if client_auth.requires_client_certificate() && !provider_http_data.has_client_certificate() {
+            return Err("tls_client_auth and self_signed_tls_client_auth require provider_client_cert_path and provider_client_key_path".to_string());
+        }
 
-        let openid_client = {
-            CoreClient::from_provider_metadata(
-                provider_metadata, 
-                ClientId::new(config_settings_data.client.as_str().to_string()), 
-                Some(ClientSecret::new(config_settings_data.client_secret.as_str().to_string()))
-            ).set_redirect_uri(redirect_url)
-        }; 
+        let request_signer = RequestObjectSigner::new(&config_settings_data)?;
 
-        Data::new(openid_client)
+        let provider_client = Data::new(ProviderClient::new(client_auth, request_signer, provider_http_data.clone()));
+        if config_settings_data.lazy_discovery {
+            spawn_discovery_task(provider_client.clone(), config_settings_data.clone());
+        }
+        else {
+            with_backoff(&config_settings_data, "provider discovery", || async {
+                traced("provider discovery", SpanKind::Client, provider_client.get(&config_settings_data)).await?;
+                return Ok(());
+            }).await?;
+        }
+        provider_client
     };
 
+    // Keeps the provider metadata and signing keys current without a restart
+    spawn_refresh_task(openid_client_data.clone(), config_settings_data.clone());
+
     let cookie_key = {
         let key_bytes = match <[u8; 64]>::from_hex(config_settings_data.secret_cookie_hex_key.as_str()) {
             Ok(bytes) => bytes,
             Err(error) => return Err(format!("{:?}", error)),
         };
 
+        // The DPoP private keys in the sessions are sealed with a key derived from the same secret
+        if config_settings_data.dpop {
+            init_key_sealing(&key_bytes)?;
+        }
+
         Key::from(&key_bytes)
     };
 
-    let redis_store = match RedisSessionStore::new(config_settings_data.redis_address.as_str()).await {
-        Ok(store) => store,
-        Err(error) => return Err(format!("{:?}", error)),
-    };
+    let redis_store = with_backoff(&config_settings_data, "redis", || async {
+        match RedisSessionStore::new(config_settings_data.redis_address.as_str()).await {
+            Ok(store) => return Ok(TracedSessionStore::new(store)),
+            Err(error) => return Err(format!("{:?}", error)),
+        }
+    }).await?;
 
     let wrapped_redis_client = {
         let redis_client = match Client::open(config_settings_data.redis_address.as_str()) {
@@ -217,7 +281,17 @@ pub(crate) async fn init(config_settings: MainConfiguration) -> Result<(), Strin
         Data::new(Mutex::new(redis_client))
     };
 
+    // /events and the Redis pub/sub tasks behind it only run with session_events
+    let event_hub_data = if config_settings_data.session_events {
+        Some(Data::new(spawn_event_tasks(&config_settings_data)?))
+    }
+    else {
+        None
+    };
+
     let requesting_client_url = config_settings_data.requesting_client_url.clone();
+    let token_route = config_settings_data.token_route;
+    let token_route_allowed_origins = config_settings_data.token_route_allowed_origins.clone();
     let cookie_name = config_settings_data.cookie_name.clone();
     let cookie_domain = config_settings_data.cookie_domain.clone();
     let listen_address = config_settings_data.listen_address.clone();
@@ -227,23 +301,52 @@ pub(crate) async fn init(config_settings: MainConfiguration) -> Result<(), Strin
     let client_request_timeout = config_settings_data.client_request_timeout_secs as u64;
     let client_disconnect_timeout = config_settings_data.client_disconnect_timeout_secs as u64;
     let max_connections = config_settings_data.max_connections as usize;
+    let shutdown_timeout = config_settings_data.shutdown_timeout_secs;
+    let shutdown_readiness_delay = config_settings_data.shutdown_readiness_delay_secs;
+    let http_redirect_port = config_settings_data.http_redirect_port;
+    let redirect_config_settings_data = config_settings_data.clone();
+
+    // tls_cert_path and tls_key_path set = serve HTTPS directly, otherwise plain HTTP behind a TLS-terminating proxy
+    let tls_server_config = match (&config_settings_data.tls_cert_path, &config_settings_data.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => Some(new_server_config(cert_path, key_path, config_settings_data.tls_reload_interval_secs)?),
+        (None, None) => None,
+        _ => return Err("tls_cert_path and tls_key_path must be set together".to_string()),
+    };
 
-    match HttpServer::new(
+    let http_server = HttpServer::new(
         move || {App::new()
             .app_data(config_settings_data.clone())
             .app_data(openid_client_data.clone())
+            .app_data(provider_http_data.clone())
             .app_data(wrapped_redis_client.clone())
-            .wrap(Cors::default()
+            .app_data(lifecycle_data.clone())
+            .wrap({
+                let mut cors = Cors::default()
                 .allowed_origin(requesting_client_url.as_str())
                 .allowed_methods(vec!["GET"])
-                .allowed_headers(vec![AUTHORIZATION, ACCEPT, CONTENT_TYPE])
-                .supports_credentials()
-            )
+                .allowed_headers(vec![AUTHORIZATION, ACCEPT, CONTENT_TYPE, HeaderName::from_static(REQUEST_ID_HEADER), HeaderName::from_static("x-csrf-token")])
+                .expose_headers(vec![HeaderName::from_static(REQUEST_ID_HEADER)])
+                .supports_credentials();
+                // The trusted frontends of /token, only for /token and the /sessionstatus call that hands out the CSRF token
+                // The /token handler checks the origin again
+                if token_route {
+                    let token_origins: Vec<String> = token_route_allowed_origins.iter().map(|origin| origin.trim_end_matches('/').to_string()).collect();
+                    cors = cors.allowed_origin_fn(move |origin, request_head| {
+                        let path = request_head.uri.path();
+                        if path != "/token" && path != "/sessionstatus" {
+                            return false;
+                        }
+                        return token_origins.iter().any(|allowed| allowed.as_bytes() == origin.as_bytes());
+                    });
+                }
+                cors
+            })
+            .wrap(from_fn(track_session_expiry)) // Inside SessionMiddleware, sees every write before it is saved
             .wrap(SessionMiddleware::builder(redis_store.clone(), cookie_key.clone(),)
                 .cookie_name(cookie_name.clone())
                 .cookie_domain(Some(cookie_domain.clone()))
                 .cookie_secure(true).cookie_http_only(true).cookie_same_site(SameSite::None)
-                .session_lifecycle(PersistentSession::default().session_ttl(cookieTimeDuration::days(7)))
+                .session_lifecycle(PersistentSession::default().session_ttl(cookieTimeDuration::days(SESSION_TTL_DAYS)))
                 .build(),
             )
             .wrap(DefaultHeaders::new()
@@ -252,11 +355,25 @@ pub(crate) async fn init(config_settings: MainConfiguration) -> Result<(), Strin
                 .add(("X-Content-Type-Options", "nosniff"))
                 .add(("Referrer-Policy", "no-referrer")),
             )
+            .wrap(from_fn(track_in_flight))
+            .wrap(from_fn(trace_request))
+            .wrap(from_fn(assign_request_id)) // Outermost, so every log line of the request carries the id
             .route("/login", web::get().to(login_handler))
             .route("/callback", web::get().to(callback_handler))
+            .route("/callback", web::post().to(callback_post_handler))
+            .route("/stepup", web::get().to(stepup_handler))
             .route("/sessionstatus", web::get().to(sessionstatus_handler))
             .route("/details", web::get().to(user_details_handler))
             .route("/logout", web::get().to(logout_handler))
+            .route("/readyz", web::get().to(readyz_handler))
+            .configure(|service_config| {
+                if let Some(event_hub) = &event_hub_data {
+                    service_config.app_data(event_hub.clone()).route("/events", web::get().to(events_handler));
+                }
+                if token_route {
+                    service_config.route("/token", web::get().to(token_handler));
+                }
+            })
         }
     )
     .workers(workers)
@@ -264,15 +381,58 @@ pub(crate) async fn init(config_settings: MainConfiguration) -> Result<(), Strin
     .client_request_timeout(timeDuration::from_secs(client_request_timeout))
     .client_disconnect_timeout(timeDuration::from_secs(client_disconnect_timeout))
     .max_connections(max_connections)
-    .bind((listen_address.as_str(), listen_port)) {
-        Ok(server) => {
-            match server.run().await {
-                Ok(_) =>return Ok(()),
+    .shutdown_timeout(shutdown_timeout)
+    .disable_signals(); // SIGTERM and SIGINT are handled by spawn_signal_task
+
+    let bound_server = match tls_server_config {
+        Some(server_config) => http_server.bind_rustls_0_23((listen_address.as_str(), listen_port), server_config),
+        None => http_server.bind((listen_address.as_str(), listen_port)),
+    };
+    let running_server = match bound_server {
+        Ok(server) => server.run(),
+        Err(error) => return Err(format!("{:?}", error)),
+    };
+    let mut server_handles = vec![running_server.handle()];
+
+    // Optional plain HTTP listener that only redirects to this_server_url
+    let redirect_server = match http_redirect_port {
+        Some(redirect_port) => {
+            match HttpServer::new(
+                move || {App::new()
+                    .app_data(redirect_config_settings_data.clone())
+                    .default_service(web::to(https_redirect_handler))
+                }
+            )
+            .workers(1)
+            .shutdown_timeout(shutdown_timeout)
+            .disable_signals()
+            .bind((listen_address.as_str(), redirect_port)) {
+                Ok(server) => {
+                    let running_redirect_server = server.run();
+                    server_handles.push(running_redirect_server.handle());
+                    Some(running_redirect_server)
+                }
                 Err(error) => return Err(format!("{:?}", error)),
             }
         }
-        Err(error) => return Err(format!("{:?}", error)),
+        None => None,
+    };
+
+    spawn_signal_task(lifecycle, server_handles, shutdown_readiness_delay)?;
+
+    let (server_result, redirect_result) = tokio::join!(running_server, async {
+        match redirect_server {
+            Some(server) => return server.await,
+            None => return Ok(()),
+        }
+    });
+    if let Err(error) = server_result {
+        return Err(format!("{:?}", error));
+    }
+    if let Err(error) = redirect_result {
+        return Err(format!("{:?}", error));
     }
+    return Ok(());
 }    
 
 ////////// END OF FILE //////////
\ No newline at end of file
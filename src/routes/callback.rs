@@ -14,30 +14,168 @@
 //! ---------------------------------------- //
 
 use crate::OpenidClientData;
-use crate::tools::error::Error;
+use crate::tools::error::{Error, ErrorCode};
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::dpop::{is_dpop_token_type, DpopHttp, DpopKey, DPOP_KEY_SESSION};
 use crate::tools::settings::MainConfiguration;
-use better_logger::logger::debugx;
+use crate::tools::responsemode::{decode_jarm_response, JarmError};
+use crate::tools::claims::username_from_claims;
+use crate::tools::requestid::request_id;
+use crate::tools::audit::{audit, start_audit_session, AuditEvent};
+use crate::tools::telemetry::traced;
+use crate::tools::events::{publish, SessionEventKind};
+use crate::tools::sessioninfo::store_scopes;
+use crate::tools::tokenexchange::EXCHANGED_TOKENS_SESSION;
+use better_logger::logger::{debugx, warn};
 use std::collections::HashMap;
+use url::Url;
 use actix_web::HttpResponse;
-use actix_web::web::{Query, Data};
+use actix_web::web::{Query, Form, Data};
 use actix_session::Session;
-use openidconnect::{AuthorizationCode, OAuth2TokenResponse, TokenResponse, Nonce, PkceCodeVerifier};
-use openidconnect::reqwest::ClientBuilder;
-use openidconnect::reqwest::redirect::Policy;
+use openidconnect::{AuthorizationCode, OAuth2TokenResponse, TokenResponse, Nonce, PkceCodeVerifier, ClaimsVerificationError, SignatureVerificationError};
+use opentelemetry::trace::SpanKind;
 use chrono::Utc;
-use std::time::Duration as timeDuration;
+use uuid::Uuid;
 use chrono::Duration as chronoDuration;
 
 const HANDLER: &str = "callback"; // Used for error logging
 
+// GET /callback, response_mode query (the default), jwt and query.jwt
 pub(crate) async fn callback_handler(
     config_settings: Data<MainConfiguration>,  
     session: Session, 
     client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
     query: Query<HashMap<String, String>>, 
 ) -> HttpResponse {
 
-    let rurl = &config_settings.requesting_client_url; // used for error logging
+    if !config_settings.response_mode.allows_get() {
+        return Error::send(session, &config_settings.error_redirect_url, HANDLER, ErrorCode::InvalidRequest, "authorization response must be a form_post", None);
+    }
+    return handle_callback(config_settings, session, client_data, provider_http, query.into_inner()).await;
+}
+
+// POST /callback, response_mode form_post, form_post.jwt and jwt
+// The provider's page posts the form cross-site, the session cookie is SameSite=None so it is sent with the POST
+pub(crate) async fn callback_post_handler(
+    config_settings: Data<MainConfiguration>,  
+    session: Session, 
+    client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    form: Form<HashMap<String, String>>, 
+) -> HttpResponse {
+
+    if !config_settings.response_mode.allows_post() {
+        return Error::send(session, &config_settings.error_redirect_url, HANDLER, ErrorCode::InvalidRequest, "authorization response must be in the query", None);
+    }
+    return handle_callback(config_settings, session, client_data, provider_http, form.into_inner()).await;
+}
+
+async fn handle_callback(
+    config_settings: Data<MainConfiguration>,  
+    session: Session, 
+    client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    received: HashMap<String, String>, 
+) -> HttpResponse {
+
+    let rurl = &config_settings.error_redirect_url; // used for error redirects
+
+    // A step-up starts from a logged in session, until its checks pass a failure keeps that login
+    let stepup = {
+        let requested = matches!(session.get::<String>("stepup_acr"), Ok(Some(_))) || matches!(session.get::<i64>("auth_requested_at"), Ok(Some(_)));
+        requested && matches!(session.get::<String>("user_id"), Ok(Some(_)))
+    };
+
+    // One snapshot of the client and the discovery extras for the whole callback, taken again only after a key refresh
+    let mut provider = match client_data.get(&config_settings).await {
+        Ok(provider) => provider,
+        Err(error) => return fail(session, rurl, stepup, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error)),
+    };
+
+    // JARM: the response parameters are claims of a signed JWT, verified before anything else is read
+    let query = {
+        if config_settings.response_mode.is_jwt() {
+            let response = match received.get("response") {
+                Some(response) => response,
+                None => return fail(session, rurl, stepup, ErrorCode::InvalidRequest, "no JARM response", None),
+            };
+            let client_id = config_settings.client.as_str();
+
+            match decode_jarm_response(response, &provider.extras.signing_keys, &provider.extras.jarm_algorithms, &config_settings.issuer_url, client_id) {
+                Ok(params) => params,
+                Err(JarmError::Invalid(error)) => return fail(session, rurl, stepup, ErrorCode::InvalidResponse, "JARM response failed", Error::fmt(error)),
+                Err(JarmError::NoMatchingKey) => {
+                    // Signed with a key that is not in the current JWKS, the provider may have rotated its keys
+                    if let Err(error) = client_data.refresh_for_unknown_key(&config_settings).await {
+                        return fail(session, rurl, stepup, ErrorCode::ProviderUnavailable, "provider refresh failed", Error::fmt(error));
+                    }
+                    provider = match client_data.get(&config_settings).await {
+                        Ok(provider) => provider,
+                        Err(error) => return fail(session, rurl, stepup, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error)),
+                    };
+                    match decode_jarm_response(response, &provider.extras.signing_keys, &provider.extras.jarm_algorithms, &config_settings.issuer_url, client_id) {
+                        Ok(params) => params,
+                        Err(JarmError::NoMatchingKey) => return fail(session, rurl, stepup, ErrorCode::InvalidResponse, "JARM response failed", Error::fmt("no matching key")),
+                        Err(JarmError::Invalid(error)) => return fail(session, rurl, stepup, ErrorCode::InvalidResponse, "JARM response failed", Error::fmt(error)),
+                    }
+                }
+            }
+        } else {
+            received
+        }
+    };
+
+    // RFC 9207 mix-up defense: the response must come from the expected issuer, error responses included
+    // Required when the provider advertises it, checked whenever it is present
+    let iss_required = match provider.extras.authorization_response_iss_parameter_supported {
+        Some(supported) => supported,
+        None => false,
+    };
+    match query.get("iss") {
+        Some(iss) => {
+            if iss != &config_settings.issuer_url {
+                return fail(session, rurl, stepup, ErrorCode::InvalidResponse, "issuer mismatch", Error::fmt(format!("expected {:?}, got {:?}", config_settings.issuer_url, iss)));
+            }
+        }
+        None => {
+            if iss_required {
+                return fail(session, rurl, stepup, ErrorCode::InvalidResponse, "no iss in query", None);
+            }
+        }
+    }
+
+    // Silent login (prompt=none): the provider needs the user, the frontend falls back to a normal login
+    let silent_login = match session.get::<bool>("silent_login") {
+        Ok(Some(silent)) => silent,
+        _ => false,
+    };
+    if let Some(error) = query.get("error") {
+        if silent_login && matches!(error.as_str(), "login_required" | "interaction_required" | "consent_required" | "account_selection_required") {
+            match (query.get("state"), session.get::<String>("state")) {
+                (Some(returned_state), Ok(Some(extracted_state))) if returned_state == &extracted_state => {}
+                _ => return fail(session, rurl, stepup, ErrorCode::StateMismatch, "silent login state mismatch", None),
+            }
+            for key in ["state", "nonce", "pkce_verifier", "silent_login", "stepup_acr", "auth_requested_at"] {
+                session.remove(key);
+            }
+
+            let mut redirect_url = match Url::parse(&config_settings.requesting_client_url) {
+                Ok(url) => url,
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::InternalError, "requesting_client_url failed", Error::fmt(error)),
+            };
+            redirect_url.query_pairs_mut().append_pair("silent_login", error);
+
+            audit(AuditEvent::LoginFailure, &session, Some(error.as_str()));
+            debugx!("[{}] callback silent login not possible: {}", request_id(), error);
+            return HttpResponse::Found()
+            .insert_header(("Location", redirect_url.as_str()))
+            .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+            .insert_header(("Pragma", "no-cache"))
+            .finish();
+        }
+    }
+    session.remove("silent_login");
 
     // Check if the provider returned an error in the query string
     if let Some(error) = query.get("error") {
@@ -45,13 +183,13 @@ pub(crate) async fn callback_handler(
             Some(desc) => desc,
             None => ""
         };
-        return Error::send(session, rurl, HANDLER, &format!("oauth_error: {:?}", description), Error::fmt(error));
+        return fail(session, rurl, stepup, ErrorCode::from_oauth_error(error), &format!("oauth_error: {:?}", description), Error::fmt(error));
     }
 
     // Get the state returned in the query string
     let returned_state = match query.get("state") {
         Some(state) => state,
-        None => return Error::send(session, rurl, HANDLER, "no state in query", None),
+        None => return fail(session, rurl, stepup, ErrorCode::StateMismatch, "no state in query", None),
     };
 
     // Get the state that login_handler inserted into the session
@@ -59,97 +197,106 @@ pub(crate) async fn callback_handler(
         Ok(option) => {
             match option {
                 Some(state) => state,
-                None => return Error::send(session, rurl, HANDLER, "no state in session", None),
+                None => return fail(session, rurl, stepup, ErrorCode::NoPendingLogin, "no state in session", None),
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_state failed", Error::fmt(error)),
+        Err(error) => return fail(session, rurl, stepup, ErrorCode::SessionError, "extracted_state failed", Error::fmt(error)),
     };
 
     // The 2 state values must match
     if returned_state != &extracted_state {
-        return Error::send(session, rurl, HANDLER, "state mismatch", None);
+        return fail(session, rurl, stepup, ErrorCode::StateMismatch, "state mismatch", None);
     }
     else {
         session.remove("state"); // No longer needed
     }
 
+    // DPoP: a new key for every session, the provider binds the tokens to it
+    let dpop_key = {
+        if config_settings.dpop {
+            match DpopKey::generate() {
+                Ok(key) => Some(key),
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::InternalError, "dpop key failed", Error::fmt(error)),
+            }
+        } else {
+            None
+        }
+    };
+
     let token_response = {
         let token_request = {
             let auth_code = match query.get("code") {
                 Some(code) => code, // From query string
-                None => return Error::send(session, rurl, HANDLER, "auth_code failed", None),
+                None => return fail(session, rurl, stepup, ErrorCode::InvalidRequest, "auth_code failed", None),
             };
 
             let pkce_verifier: String = match session.get("pkce_verifier") {
                 Ok(verifier) => {
                     match verifier {
                         Some(ver) => ver, // login_handler inserted this into the session
-                        None => return Error::send(session, rurl, HANDLER, "pkce_verifier is None", None),
+                        None => return fail(session, rurl, stepup, ErrorCode::NoPendingLogin, "pkce_verifier is None", None),
                     }
                 }
-                Err(error) => return Error::send(session, rurl, HANDLER, "pkce_verifier failed", Error::fmt(error)),
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::SessionError, "pkce_verifier failed", Error::fmt(error)),
             };
 
-            match client_data.exchange_code(AuthorizationCode::new(auth_code.to_string())) { // build the token request
+            let mut request = match provider.client.exchange_code(AuthorizationCode::new(auth_code.to_string())) { // build the token request
                 Ok(request) => request.set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier)),
-                Err(error) => return Error::send(session, rurl, HANDLER, "token_request failed", Error::fmt(error)),
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::TokenExchangeFailed, "token_request failed", Error::fmt(error)),
+            };
+
+            // client_secret_jwt and private_key_jwt add a signed client assertion, the audience is the token endpoint
+            let token_url = match provider.client.token_uri() {
+                Some(url) => url.as_str(),
+                None => return fail(session, rurl, stepup, ErrorCode::InternalError, "no token endpoint", None),
+            };
+            match client_data.client_auth().extra_params(token_url) {
+                Ok(params) => {
+                    for (name, value) in params {
+                        request = request.add_extra_param(name, value);
+                    }
+                }
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::InternalError, "client assertion failed", Error::fmt(error)),
             }
+
+            request
         }; 
 
-        let http_client = match ClientBuilder::new().redirect(Policy::none()).timeout(timeDuration::from_secs(10)).build() {
-            Ok(http_client) => http_client,
-            Err(error) => return Error::send(session, rurl, HANDLER, "http_client failed", Error::fmt(error)),
+        let token_result = match &dpop_key {
+            Some(key) => traced("token exchange", SpanKind::Client, token_request.request_async(&DpopHttp::new(provider_http.get_ref(), key))).await,
+            None => traced("token exchange", SpanKind::Client, token_request.request_async(provider_http.get_ref())).await,
         };
 
-        match token_request.request_async(&http_client).await {
+        match token_result {
             Ok(response) => {
                 session.remove("pkce_verifier"); // No longer needed
                 response // Use the request and the http client to get the response
             }
-            Err(error) => return Error::send(session, rurl, HANDLER, "token_response failed", Error::fmt(error)),
+            Err(error) => return fail(session, rurl, stepup, ErrorCode::TokenExchangeFailed, "token_response failed", Error::fmt(error)),
         }
     };
 
-    // Get expiry
-    if let Some(expires_in) = token_response.expires_in() {
-        let expiry = match chronoDuration::from_std(expires_in) {
-            Ok(time) => time,
-            Err(error) => return Error::send(session, rurl, HANDLER, "expiry failed", Error::fmt(error)),
-        };
-        let new_expiry = (Utc::now() + expiry).timestamp();
- 
-        if let Err(error) = session.insert("token_expiry", new_expiry) {
-            return Error::send(session, rurl, HANDLER, "failed to store token_expiry", Error::fmt(error));
-        }
-    } 
-    else {
-        return Error::send(session, rurl, HANDLER, "missing expiry", None);
-    }
+    // Everything is checked before anything is written, a failed step-up leaves the current login as it was
 
-    // Add access token to the session
-    if let Err(error) = session.insert("access_token", token_response.access_token().secret()) {
-        return Error::send(session, rurl, HANDLER, "failed to store access_token", Error::fmt(error));
-    }
-
-    let refresh_token = match token_response.refresh_token() {
-        Some(token) => token,
-        None => return Error::send(session, rurl, HANDLER, "no refresh_token", None),
+    let token_expiry = match token_response.expires_in() {
+        Some(expires_in) => {
+            match chronoDuration::from_std(expires_in) {
+                Ok(time) => (Utc::now() + time).timestamp(),
+                Err(error) => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "expiry failed", Error::fmt(error)),
+            }
+        }
+        None => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "missing expiry", None),
     };
 
-    // Add refresh token to the session
-    if let Err(error) = session.insert("refresh_token", refresh_token.secret()) {
-        return Error::send(session, rurl, HANDLER, "failed to store refresh_token", Error::fmt(error));
+    // Without a refresh token (allow_missing_refresh_token), the session ends when the access token expires
+    if token_response.refresh_token().is_none() && !config_settings.allow_missing_refresh_token {
+        return fail(session, rurl, stepup, ErrorCode::InvalidToken, "no refresh_token", None);
     }
 
     let identification_token = match TokenResponse::id_token(&token_response) {
         Some(id_token) => id_token,
-        None => return Error::send(session, rurl, HANDLER, "identification_token failed", None),
+        None => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "identification_token failed", None),
     };
-    
-    // Add id token to the session
-    if let Err(error) = session.insert("id_token", identification_token.to_string()) {
-        return Error::send(session, rurl, HANDLER, "failed to store id_token", Error::fmt(error));
-    }
 
     // verified_claims is a strongly-typed claims object, a trusted set of user info
     let verified_claims = {
@@ -157,41 +304,176 @@ pub(crate) async fn callback_handler(
             Ok(option) => {
                 match option {
                     Some(nonce) => nonce,
-                    None => return Error::send(session, rurl, HANDLER, "no nonce", None),
+                    None => return fail(session, rurl, stepup, ErrorCode::NoPendingLogin, "no nonce", None),
                 }
             }
-            Err(error) => return Error::send(session, rurl, HANDLER, "stored_nonce failed", Error::fmt(error)),
+            Err(error) => return fail(session, rurl, stepup, ErrorCode::SessionError, "stored_nonce failed", Error::fmt(error)),
         };
 
-        let verifier = client_data.id_token_verifier();
+        let verifier = provider.client.id_token_verifier();
         let nonce = Nonce::new(stored_nonce.clone()); 
 
         // The openidconnect crate uses the verifier and nonce to validate the claims
         match identification_token.claims(&verifier, &nonce) {
             Ok(claims) => claims,
-            Err(error) => return Error::send(session, rurl, HANDLER, "id_token verification failed", Error::fmt(error)),
+            Err(ClaimsVerificationError::SignatureVerification(SignatureVerificationError::NoMatchingKey)) => {
+                // The provider may have rotated its signing keys, refresh the JWKS and verify one more time
+                if let Err(error) = client_data.refresh_for_unknown_key(&config_settings).await {
+                    return fail(session, rurl, stepup, ErrorCode::ProviderUnavailable, "jwks refresh failed", Error::fmt(error));
+                }
+
+                let refreshed = match client_data.get(&config_settings).await {
+                    Ok(provider) => provider,
+                    Err(error) => return fail(session, rurl, stepup, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error)),
+                };
+                let refreshed_verifier = refreshed.client.id_token_verifier();
+                match identification_token.claims(&refreshed_verifier, &nonce) {
+                    Ok(claims) => claims,
+                    Err(error) => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "id_token verification failed", Error::fmt(error)),
+                }
+            }
+            Err(error) => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "id_token verification failed", Error::fmt(error)),
         }
     };
 
-    session.remove("nonce"); // No longer needed
+    // The first claim of username_claims that the id token carries
+    let username = match username_from_claims(&config_settings.username_claims, verified_claims) {
+        Some(name) => name,
+        None => return fail(session, rurl, stepup, ErrorCode::InvalidToken, "no username claim", Error::fmt(format!("{:?}", config_settings.username_claims))),
+    };
 
-    let username = match verified_claims.preferred_username() {
-        Some(name) => name.to_string(),
-        None => return Error::send(session, rurl, HANDLER, "no preferred_username", None),
+    // Authentication assurance of this login, reported by sessionstatus_handler
+    let acr = verified_claims.auth_context_ref().map(|acr| acr.as_str().to_string());
+    let amr: Vec<String> = match verified_claims.auth_method_refs() {
+        Some(methods) => methods.iter().map(|method| method.as_str().to_string()).collect(),
+        None => Vec::new(),
     };
+    let auth_time = verified_claims.auth_time().map(|time| time.timestamp());
+
+    // Step-up: the provider must have met one of the requested acr values
+    match session.get::<String>("stepup_acr") {
+        Ok(Some(requested)) => {
+            let satisfied = match &acr {
+                Some(achieved) => requested.split_whitespace().any(|value| value == achieved),
+                None => false,
+            };
+            if !satisfied {
+                return fail(session, rurl, stepup, ErrorCode::StepUpFailed, "step-up acr not met", Error::fmt(format!("requested {:?}, got {:?}", requested, acr)));
+            }
+        }
+        Ok(None) => {}
+        Err(error) => return fail(session, rurl, stepup, ErrorCode::SessionError, "stepup_acr failed", Error::fmt(error)),
+    }
+
+    // max_age: the user must have authenticated after the request was sent (60 seconds of clock skew)
+    match session.get::<i64>("auth_requested_at") {
+        Ok(Some(requested_at)) => {
+            match auth_time {
+                Some(time) => {
+                    if time < requested_at - 60 {
+                        return fail(session, rurl, stepup, ErrorCode::StepUpFailed, "step-up auth_time too old", Error::fmt(format!("auth_time {} before {}", time, requested_at)));
+                    }
+                }
+                None => return fail(session, rurl, stepup, ErrorCode::StepUpFailed, "step-up no auth_time", None),
+            }
+        }
+        Ok(None) => {}
+        Err(error) => return fail(session, rurl, stepup, ErrorCode::SessionError, "auth_requested_at failed", Error::fmt(error)),
+    }
+
+    // All checks passed, from here on the new login replaces the session contents
+    for key in ["nonce", "stepup_acr", "auth_requested_at"] {
+        session.remove(key); // No longer needed
+    }
+
+    // Keep the key only if the provider actually bound the tokens, sessionstatus_handler uses it for the refresh
+    match dpop_key {
+        Some(key) if is_dpop_token_type(token_response.token_type()) => {
+            let sealed_key = match key.to_session_value() {
+                Ok(value) => value,
+                Err(error) => return Error::send(session, rurl, HANDLER, ErrorCode::InternalError, "failed to seal dpop_key", Error::fmt(error)),
+            };
+            if let Err(error) = session.insert(DPOP_KEY_SESSION, sealed_key) {
+                return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store dpop_key", Error::fmt(error));
+            }
+        }
+        Some(_) => {
+            session.remove(DPOP_KEY_SESSION);
+            warn!("[{}] (callback) dpop is enabled but the provider returned a {:?} token, the tokens are not sender-constrained", request_id(), token_response.token_type());
+        }
+        None => {}
+    }
+
+    if let Err(error) = session.insert("token_expiry", token_expiry) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store token_expiry", Error::fmt(error));
+    }
+
+    // Add access token to the session
+    if let Err(error) = session.insert("access_token", token_response.access_token().secret()) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store access_token", Error::fmt(error));
+    }
+
+    // Tokens exchanged for the previous access token (step-up), they may carry the old rights
+    session.remove(EXCHANGED_TOKENS_SESSION);
+
+    // Granted scopes, for sessionstatus_fields
+    session.remove("scopes"); // From an earlier login in the same session
+    let scopes = token_response.scopes().map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect());
+    if let Err(error) = store_scopes(&session, scopes) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store scopes", Error::fmt(error));
+    }
+
+    // Add refresh token to the session
+    match token_response.refresh_token() {
+        Some(refresh_token) => {
+            if let Err(error) = session.insert("refresh_token", refresh_token.secret()) {
+                return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store refresh_token", Error::fmt(error));
+            }
+        }
+        None => {
+            session.remove("refresh_token"); // From an earlier login in the same session
+        }
+    }
+
+    // Add id token to the session
+    if let Err(error) = session.insert("id_token", identification_token.to_string()) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store id_token", Error::fmt(error));
+    }
 
     // Insert username to be used by user_details_handler
     if let Err(error) = session.insert("username", username) {
-        return Error::send(session, rurl, HANDLER, "failed to store username", Error::fmt(error));
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store username", Error::fmt(error));
     }
 
     // Insert user id to be used by user_details_handler
     if let Err(error) = session.insert("user_id", verified_claims.subject().to_string()) {
-        return Error::send(session, rurl, HANDLER, "failed to store user_id", Error::fmt(error));
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store user_id", Error::fmt(error));
+    }
+
+    if let Err(error) = session.insert("acr", acr) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store acr", Error::fmt(error));
+    }
+    if let Err(error) = session.insert("amr", amr) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store amr", Error::fmt(error));
+    }
+    if let Err(error) = session.insert("auth_time", auth_time) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store auth_time", Error::fmt(error));
     }
 
+    // /token requires this value in X-CSRF-Token, the web client reads it from /sessionstatus
+    if config_settings.token_route {
+        if let Err(error) = session.insert("csrf_token", Uuid::new_v4().simple().to_string()) {
+            return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store csrf_token", Error::fmt(error));
+        }
+    }
+    if let Err(error) = start_audit_session(&session) {
+        return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "failed to store audit_session_id", Error::fmt(error));
+    }
+    audit(AuditEvent::LoginSuccess, &session, None);
+    publish(SessionEventKind::LoggedIn, &session);
+
     // If all is good, send user to you web page
-    debugx!("callback successful");
+    debugx!("[{}] callback successful", request_id());
     return HttpResponse::Found()
     .insert_header(("Location", config_settings.requesting_client_url.as_str()))
     .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
@@ -199,4 +481,16 @@ pub(crate) async fn callback_handler(
     .finish();
 }
 
+// Before the new tokens are written: a failed step-up drops only its pending request, a failed login purges the session
+fn fail(session: Session, rurl: &str, stepup: bool, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+    if stepup {
+        for key in ["state", "nonce", "pkce_verifier", "stepup_acr", "auth_requested_at"] {
+            session.remove(key);
+        }
+        audit(AuditEvent::LoginFailure, &session, Some(code.as_str()));
+        return Error::redirect(rurl, HANDLER, code, msg, err);
+    }
+    return Error::send(session, rurl, HANDLER, code, msg, err);
+}
+
 ////////// END OF FILE //////////
\ No newline at end of file
@@ -13,8 +13,9 @@
 //!
 //! ---------------------------------------- //
 
-use crate::tools::error::Error;
+use crate::tools::error::{Error, ErrorCode};
 use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::request_id;
 use better_logger::logger::debugx;
 use actix_web::HttpResponse;
 use actix_web::web::Data;
@@ -34,8 +35,6 @@ pub(crate) async fn user_details_handler(
     session: Session
 ) -> HttpResponse {
 
-    let rurl = &config_settings.requesting_client_url; // used for error logging
-
     let default_user_details = UserDetails { 
         username: config_settings.default_username.clone(),
         user_id: config_settings.default_user_id.clone(),
@@ -48,14 +47,14 @@ pub(crate) async fn user_details_handler(
                 Some(name) => name,
                 None => {
                     if config_settings.user_details_fail_when_not_authenticated {
-                        return Error::send(session, rurl, HANDLER, "no username in session", None)
+                        return Error::json(session, HANDLER, ErrorCode::NotAuthenticated, "no username in session", None)
                     } else {
                         return HttpResponse::Ok().json(default_user_details);
                     }
                 }
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_username failed", Error::fmt(error)),
+        Err(error) => return Error::json(session, HANDLER, ErrorCode::SessionError, "extracted_username failed", Error::fmt(error)),
     };
 
     // Determine what to do based on user_id in session
@@ -65,14 +64,14 @@ pub(crate) async fn user_details_handler(
                 Some(id) => id,
                 None => {
                     if config_settings.user_details_fail_when_not_authenticated {
-                        return Error::send(session, rurl, HANDLER, "no user_id in session", None)
+                        return Error::json(session, HANDLER, ErrorCode::NotAuthenticated, "no user_id in session", None)
                     } else {
                         return HttpResponse::Ok().json(default_user_details);
                     }
                 }
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_user_id failed", Error::fmt(error)),
+        Err(error) => return Error::json(session, HANDLER, ErrorCode::SessionError, "extracted_user_id failed", Error::fmt(error)),
     };
 
     let user_details = UserDetails { 
@@ -81,7 +80,7 @@ pub(crate) async fn user_details_handler(
     };
 
     // If all is good, return user details
-    debugx!("user_details successful");
+    debugx!("[{}] user_details successful", request_id());
     return HttpResponse::Ok().json(user_details);
 }
 
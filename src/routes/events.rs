@@ -0,0 +1,170 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::lifecycle::Lifecycle;
+use crate::tools::audit::session_id_hash;
+use crate::tools::events::{EventHub, SessionEvent, SessionEventKind};
+use better_logger::logger::warn;
+use actix_web::{Error as actixError, HttpResponse};
+use actix_web::web::{Bytes, Data};
+use actix_session::Session;
+use futures_util::stream::{once, unfold, StreamExt};
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{sleep, Duration as tokioDuration};
+use chrono::Utc;
+use serde_json::json;
+
+const KEEPALIVE_SECS: i64 = 15; // Keeps proxies from closing an idle stream
+
+// Server-Sent Events for the session of the caller: logged_in, refreshed, expiring_soon, logged_out
+// Replaces polling /sessionstatus from every tab, a logout in one tab or on another instance reaches all of them
+pub(crate) async fn events_handler(
+    config_settings: Data<MainConfiguration>,
+    session: Session,
+    event_hub: Data<EventHub>,
+    lifecycle: Data<Lifecycle>,
+) -> HttpResponse {
+
+    let user_id = match session.get::<String>("user_id") {
+        Ok(Some(user_id)) => Some(user_id),
+        _ => None,
+    };
+    let logged_in = matches!(session.get::<String>("access_token"), Ok(Some(_)));
+    let user_id = match user_id {
+        Some(user_id) if logged_in => user_id,
+        _ => {
+            // The web client closes its EventSource on logged_out, otherwise the browser reconnects
+            let stream = once(async { Ok::<Bytes, actixError>(sse_event("logged_out", None)) });
+            return event_stream_response().streaming(stream);
+        }
+    };
+    let token_expiry = match session.get::<i64>("token_expiry") {
+        Ok(value) => value,
+        Err(_) => None,
+    };
+
+    let state = EventStream {
+        receiver: event_hub.subscribe(),
+        user_id: user_id,
+        session: session_id_hash(&session),
+        token_expiry: token_expiry,
+        warning_sent: false,
+        early_refresh_skew_secs: config_settings.early_refresh_skew_secs,
+        lifecycle: lifecycle,
+        closed: false,
+    };
+
+    let first = once(async move { Ok::<Bytes, actixError>(sse_event("logged_in", token_expiry)) });
+    let rest = unfold(state, |mut state| async move {
+        match state.next_chunk().await {
+            Some(chunk) => return Some((Ok::<Bytes, actixError>(chunk), state)),
+            None => return None,
+        }
+    });
+    return event_stream_response().streaming(first.chain(rest));
+}
+
+struct EventStream {
+    receiver: Receiver<SessionEvent>,
+    user_id: String,
+    session: Option<String>,
+    token_expiry: Option<i64>,
+    warning_sent: bool,
+    early_refresh_skew_secs: i64,
+    lifecycle: Data<Lifecycle>,
+    closed: bool,
+}
+
+impl EventStream {
+    // expiring_soon is due when /sessionstatus would refresh (or end a session without a refresh token)
+    fn warning_at(&self) -> Option<i64> {
+        if self.warning_sent {
+            return None;
+        }
+        match self.token_expiry {
+            Some(expiry) => return Some(expiry - self.early_refresh_skew_secs),
+            None => return None,
+        }
+    }
+
+    // None ends the stream
+    async fn next_chunk(&mut self) -> Option<Bytes> {
+        if self.closed {
+            return None;
+        }
+        loop {
+            let warning_at = self.warning_at();
+            let wait = match warning_at {
+                Some(at) => (at - Utc::now().timestamp()).clamp(0, KEEPALIVE_SECS),
+                None => KEEPALIVE_SECS,
+            };
+
+            tokio::select! {
+                received = self.receiver.recv() => {
+                    let event = match received {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(skipped)) => {
+                            // A skipped event may have been logged_out, the browser reconnects and the handler reads the session again
+                            warn!("(events) stream fell behind by {} event(s), closing it", skipped);
+                            return None;
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+                    if !event.is_for(&self.user_id, self.session.as_deref()) {
+                        continue;
+                    }
+                    match event.event {
+                        SessionEventKind::LoggedOut => self.closed = true,
+                        SessionEventKind::LoggedIn | SessionEventKind::Refreshed => {
+                            self.token_expiry = event.token_expiry;
+                            self.warning_sent = false;
+                        }
+                    }
+                    return Some(sse_event(event.event.as_str(), event.token_expiry));
+                }
+                _ = sleep(tokioDuration::from_secs(wait as u64)) => {
+                    // Streams never end on their own, end them so a shutdown does not wait for shutdown_timeout
+                    if self.lifecycle.is_shutting_down() {
+                        return None;
+                    }
+                    if let Some(at) = warning_at {
+                        if Utc::now().timestamp() >= at {
+                            self.warning_sent = true;
+                            return Some(sse_event("expiring_soon", self.token_expiry));
+                        }
+                    }
+                    return Some(Bytes::from_static(b": keepalive\n\n"));
+                }
+            }
+        }
+    }
+}
+
+fn sse_event(name: &str, token_expiry: Option<i64>) -> Bytes {
+    return Bytes::from(format!("event: {}\ndata: {}\n\n", name, json!({"token_expiry": token_expiry})));
+}
+
+fn event_stream_response() -> actix_web::HttpResponseBuilder {
+    let mut builder = HttpResponse::Ok();
+    builder
+    .content_type("text/event-stream")
+    .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+    .insert_header(("X-Accel-Buffering", "no")); // nginx would buffer the stream
+    return builder;
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
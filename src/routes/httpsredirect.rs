@@ -0,0 +1,39 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::Data;
+
+// Only served by the plain HTTP listener (http_redirect_port)
+// The target is built from this_server_url, never from the Host header
+pub(crate) async fn https_redirect_handler(
+    config_settings: Data<MainConfiguration>,
+    req: HttpRequest,
+) -> HttpResponse {
+
+    let path_and_query = match req.uri().path_and_query() {
+        Some(path) => path.as_str(),
+        None => "/",
+    };
+    let location = format!("{}{}", config_settings.this_server_url.trim_end_matches('/'), path_and_query);
+
+    return HttpResponse::PermanentRedirect()
+    .insert_header(("Location", location))
+    .insert_header(("Cache-Control", "no-store"))
+    .finish();
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
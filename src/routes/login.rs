@@ -14,14 +14,20 @@
 //! ---------------------------------------- //
 
 use crate::OpenidClientData;
-use crate::tools::error::Error;
+use crate::tools::error::{Error, ErrorCode};
+use crate::tools::par::push_authorization_request;
+use crate::tools::providerhttp::ProviderHttp;
 use crate::tools::settings::MainConfiguration;
-use better_logger::logger::debugx;
+use crate::tools::requestid::request_id;
+use better_logger::logger::{debugx, warn};
+use std::collections::HashMap;
+use std::time::Duration;
 use actix_web::HttpResponse;
-use actix_web::web::Data;
+use actix_web::web::{Query, Data};
 use actix_session::Session;
-use openidconnect::{CsrfToken, Nonce, Scope, PkceCodeChallenge};
-use openidconnect::core::CoreAuthenticationFlow;
+use openidconnect::{AuthenticationContextClass, CsrfToken, Nonce, Scope, PkceCodeChallenge};
+use openidconnect::core::{CoreAuthenticationFlow, CoreAuthPrompt};
+use chrono::Utc;
 
 const HANDLER: &str = "login"; // Used for error logging
 
@@ -29,41 +35,159 @@ pub(crate) async fn login_handler(
     config_settings: Data<MainConfiguration>, 
     session: Session, 
     client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    query: Query<HashMap<String, String>>,
 ) -> HttpResponse {
     
-    let rurl = &config_settings.requesting_client_url; // used for error logging
+    // /login?prompt=none: silent re-authentication against the provider's SSO session, only with silent_reauth
+    let mut options = AuthorizationOptions::default();
+    if config_settings.silent_reauth && query.get("prompt").map(String::as_str) == Some("none") {
+        options.prompt = Some(CoreAuthPrompt::None);
+    }
+
+    return start_authorization(config_settings, session, client_data, provider_http, HANDLER, options).await;
+}
+
+// Extra parameters of the authorization request, for step-up
+#[derive(Default)]
+pub(crate) struct AuthorizationOptions {
+    pub(crate) acr_values: Option<String>, // Space separated, callback_handler requires one of them
+    pub(crate) prompt: Option<CoreAuthPrompt>,
+    pub(crate) max_age: Option<Duration>, // callback_handler checks auth_time against it
+    pub(crate) keep_session_on_error: bool, // Step-up starts from a logged in session, a failed start must not end it
+}
+
+// The authorization code flow start, shared by /login and /stepup
+pub(crate) async fn start_authorization(
+    config_settings: Data<MainConfiguration>, 
+    session: Session, 
+    client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    handler: &str,
+    options: AuthorizationOptions,
+) -> HttpResponse {
+
+    let rurl = &config_settings.error_redirect_url; // used for error redirects
+    let keep_session = options.keep_session_on_error;
+
+    // With lazy_discovery, the first login runs discovery if the background task has not finished yet
+    let provider = match client_data.get(&config_settings).await {
+        Ok(provider) => provider,
+        Err(error) => return fail(session, rurl, handler, keep_session, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error)),
+    };
 
     // Create and insert into the session, callback_handler will validate pkce_verifier
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
     if let Err(error) = session.insert("pkce_verifier", pkce_verifier.secret()) {
-        return Error::send(session, rurl, HANDLER, "failed to store pkce_verifier", Error::fmt(error));
+        return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store pkce_verifier", Error::fmt(error));
     }
 
     // Use the openidconnect crate to build these items
     let (auth_url, csrf_token, nonce) = {
-        client_data
+        let mut request = provider.client
         .authorize_url(CoreAuthenticationFlow::AuthorizationCode, CsrfToken::new_random, Nonce::new_random,)
         .set_pkce_challenge(pkce_challenge)
         .add_scope(Scope::new("openid".to_string()))
         .add_scope(Scope::new("profile".to_string()))
         .add_scope(Scope::new("email".to_string()))
         .add_scope(Scope::new("offline_access".to_string()))
-        .add_scope(Scope::new("groups".to_string()))
-        .url()
+        .add_scope(Scope::new("groups".to_string()));
+
+        if let Some(acr_values) = &options.acr_values {
+            for acr in acr_values.split_whitespace() {
+                request = request.add_auth_context_value(AuthenticationContextClass::new(acr.to_string()));
+            }
+        }
+        if let Some(prompt) = &options.prompt {
+            request = request.add_prompt(prompt.clone());
+        }
+        if let Some(max_age) = options.max_age {
+            request = request.set_max_age(max_age);
+        }
+
+        // form_post and JARM, query is the default of the code flow and is not sent
+        match config_settings.response_mode.parameter() {
+            Some(mode) => request.add_extra_param("response_mode", mode).url(),
+            None => request.url(),
+        }
     }; 
     
     // Insert state into the session, callback_handler will validate this value
     if let Err(error) = session.insert("state", csrf_token.secret()) {
-        return Error::send(session, rurl, HANDLER, "failed to store state", Error::fmt(error));
+        return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store state", Error::fmt(error));
     }
     
     // Insert nonce into the session, callback_handler will validate this value
     if let Err(error) = session.insert("nonce", nonce.secret()) {
-        return Error::send(session, rurl, HANDLER, "failed to store nonce", Error::fmt(error));
+        return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store nonce", Error::fmt(error));
     }
     
+    // Step-up markers, callback_handler verifies acr and auth_time against them
+    // A plain login replaces a step-up that was never completed
+    match &options.acr_values {
+        Some(acr_values) => {
+            if let Err(error) = session.insert("stepup_acr", acr_values) {
+                return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store stepup_acr", Error::fmt(error));
+            }
+        }
+        None => {
+            session.remove("stepup_acr");
+        }
+    }
+    // Silent login, callback_handler treats login_required and similar errors as an answer, not a failure
+    if options.prompt == Some(CoreAuthPrompt::None) {
+        if let Err(error) = session.insert("silent_login", true) {
+            return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store silent_login", Error::fmt(error));
+        }
+    } else {
+        session.remove("silent_login");
+    }
+    match options.max_age {
+        Some(_) => {
+            if let Err(error) = session.insert("auth_requested_at", Utc::now().timestamp()) {
+                return fail(session, rurl, handler, keep_session, ErrorCode::SessionError, "failed to store auth_requested_at", Error::fmt(error));
+            }
+        }
+        None => {
+            session.remove("auth_requested_at");
+        }
+    }
+
+    // JAR: the parameters are wrapped in a signed request object, before PAR so a pushed request is signed too
+    let auth_url = {
+        match client_data.request_signer() {
+            Some(signer) => {
+                match signer.sign(&auth_url) {
+                    Ok(url) => url,
+                    Err(error) => return fail(session, rurl, handler, keep_session, ErrorCode::InternalError, "request object failed", Error::fmt(error)),
+                }
+            }
+            None => auth_url,
+        }
+    };
+
+    // PAR: the parameters go to the provider directly, the browser only sees the request_uri
+    let auth_url = {
+        if config_settings.pushed_authorization_requests {
+            match provider.extras.pushed_authorization_request_endpoint.as_deref() {
+                Some(par_endpoint) => {
+                    match push_authorization_request(&provider_http, client_data.client_auth(), par_endpoint, &config_settings.issuer_url, &auth_url).await {
+                        Ok(url) => url,
+                        Err(error) => return fail(session, rurl, handler, keep_session, ErrorCode::ProviderUnavailable, "pushed authorization request failed", Error::fmt(error)),
+                    }
+                }
+                None => {
+                    warn!("[{}] (login) pushed_authorization_requests is enabled but the provider does not advertise a pushed_authorization_request_endpoint, using a classic redirect", request_id());
+                    auth_url
+                }
+            }
+        } else {
+            auth_url
+        }
+    };
+
     // If all is good, send user to your login page
-    debugx!("login successful");
+    debugx!("[{}] {} successful", request_id(), handler);
     return HttpResponse::Found()
     .insert_header(("Location", auth_url.as_str()))
     .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
@@ -71,4 +195,12 @@ pub(crate) async fn login_handler(
     .finish();
 }
 
+// /login purges the session on failure, /stepup keeps the login it started from
+fn fail(session: Session, rurl: &str, handler: &str, keep_session: bool, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+    if keep_session {
+        return Error::redirect(rurl, handler, code, msg, err);
+    }
+    return Error::send(session, rurl, handler, code, msg, err);
+}
+
 ////////// END OF FILE //////////
\ No newline at end of file
@@ -13,8 +13,11 @@
 //!
 //! ---------------------------------------- //
 
-use crate::tools::error::Error;
+use crate::tools::error::{Error, ErrorCode};
 use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::request_id;
+use crate::tools::audit::{audit, AuditEvent};
+use crate::tools::events::{publish, SessionEventKind};
 use better_logger::logger::debugx;
 use actix_web::HttpResponse;
 use actix_web::web::Data;
@@ -28,29 +31,31 @@ pub(crate) async fn logout_handler(
     session: Session,
 ) -> HttpResponse {
 
-    let rurl = &config_settings.requesting_client_url; // used for error logging
+    let rurl = &config_settings.error_redirect_url; // used for error redirects
 
     let extracted_id_token = match session.get::<String>("id_token") {
         Ok(option) => {
             match option {
                 Some(token) => token,
-                None => return Error::send(session, rurl, HANDLER, "no id token", None),
+                None => return Error::send(session, rurl, HANDLER, ErrorCode::NotAuthenticated, "no id token", None),
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_id_token failed", Error::fmt(error)),
+        Err(error) => return Error::send(session, rurl, HANDLER, ErrorCode::SessionError, "extracted_id_token failed", Error::fmt(error)),
     };
 
     let logout_url = {
         let mut url = match Url::parse(&config_settings.logout_url) {
             Ok(url) => url,
-            Err(error) => return Error::send(session, rurl, HANDLER, "bad end session url", Error::fmt(error)),
+            Err(error) => return Error::send(session, rurl, HANDLER, ErrorCode::InternalError, "bad end session url", Error::fmt(error)),
         };
         url.query_pairs_mut().append_pair("id_token_hint", &extracted_id_token).append_pair("post_logout_redirect_uri", config_settings.requesting_client_url.as_str());
         url
     };
 
+    audit(AuditEvent::Logout, &session, None);
+    publish(SessionEventKind::LoggedOut, &session);
     session.purge();    
-    debugx!("logout successful");
+    debugx!("[{}] logout successful", request_id());
     return HttpResponse::Found()
     .insert_header(("Location", logout_url.as_str()))
     .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
@@ -18,5 +18,10 @@ pub(crate) mod callback;
 pub(crate) mod sessionstatus;
 pub(crate) mod details;
 pub(crate) mod logout;
+pub(crate) mod readyz;
+pub(crate) mod httpsredirect;
+pub(crate) mod stepup;
+pub(crate) mod events;
+pub(crate) mod token;
 
 ////////// END OF FILE //////////
\ No newline at end of file
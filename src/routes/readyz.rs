@@ -0,0 +1,76 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::OpenidClientData;
+use crate::tools::lifecycle::Lifecycle;
+use std::sync::Mutex;
+use actix_web::HttpResponse;
+use actix_web::web::Data;
+use redis::{Client, RedisResult};
+use serde_json::json;
+use tokio::time::{timeout, Duration as tokioDuration};
+
+// For orchestrators (Docker, Kubernetes), not for the web client
+pub(crate) async fn readyz_handler(
+    client_data: OpenidClientData,
+    lifecycle: Data<Lifecycle>,
+    redis_client: Data<Mutex<Client>>,
+) -> HttpResponse {
+
+    // A signal was received, the server is draining and will stop soon
+    if lifecycle.is_shutting_down() {
+        return HttpResponse::ServiceUnavailable()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({"status": "shutting_down"}));
+    }
+
+    // Discovery has not succeeded yet (lazy_discovery), logins cannot be served
+    if !client_data.is_ready() {
+        return HttpResponse::ServiceUnavailable()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({"status": "waiting_for_provider"}));
+    }
+
+    // Sessions live in Redis, without it no request can be served
+    if !redis_is_reachable(&redis_client).await {
+        return HttpResponse::ServiceUnavailable()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({"status": "waiting_for_redis"}));
+    }
+
+    return HttpResponse::Ok()
+    .insert_header(("Cache-Control", "no-store"))
+    .json(json!({"status": "ready"}));
+}
+
+// A PING on a new connection, bounded so a hanging Redis fails the probe instead of stalling it
+async fn redis_is_reachable(redis_client: &Mutex<Client>) -> bool {
+    let client = match redis_client.lock() {
+        Ok(guard) => guard.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+
+    let ping = async {
+        let mut connection = client.get_multiplexed_async_connection().await?;
+        let result: RedisResult<String> = redis::cmd("PING").query_async(&mut connection).await;
+        return result;
+    };
+    match timeout(tokioDuration::from_secs(2), ping).await {
+        Ok(Ok(_)) => return true,
+        _ => return false,
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -14,18 +14,25 @@
 //! ---------------------------------------- //
 
 use crate::OpenidClientData;
-use crate::tools::error::Error;
+use crate::tools::error::{is_transient_token_error, Error, ErrorCode};
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::dpop::{DpopHttp, DpopKey, DPOP_KEY_SESSION};
 use crate::tools::settings::MainConfiguration;
-use better_logger::logger::{debugx, error};
+use crate::tools::requestid::request_id;
+use crate::tools::audit::{audit, AuditEvent};
+use crate::tools::telemetry::traced;
+use crate::tools::events::{publish, SessionEventKind};
+use crate::tools::sessioninfo::{store_scopes, StatusField};
+use crate::tools::tokenexchange::EXCHANGED_TOKENS_SESSION;
+use better_logger::logger::{debugx, error, warn};
 use actix_web::HttpResponse;
 use actix_web::web::Data;
 use actix_session::Session;
-use openidconnect::{RefreshToken, OAuth2TokenResponse};
+use openidconnect::{RefreshToken, OAuth2TokenResponse, RequestTokenError};
+use openidconnect::core::CoreErrorResponseType;
+use opentelemetry::trace::SpanKind;
 use chrono::Utc;
-use serde_json::json;
-use openidconnect::reqwest::ClientBuilder;
-use openidconnect::reqwest::redirect::Policy;
-use std::time::Duration as timeDuration;
+use serde_json::{json, Value};
 use chrono::Duration as chronoDuration;
 
 const HANDLER: &str = "sessionstatus"; // Used for error logging
@@ -34,75 +41,150 @@ pub(crate) async fn sessionstatus_handler(
     config_settings: Data<MainConfiguration>,
     session: Session,
     client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
 ) -> HttpResponse {
 
-    let rurl = &config_settings.requesting_client_url; // used for error logging
+    match check_session(&config_settings, &session, &client_data, &provider_http, HANDLER).await {
+        Ok(SessionCheck::LoggedIn { refreshed }) => return logged_in(&session, &config_settings, refreshed),
+        Ok(SessionCheck::NotLoggedIn) => return HttpResponse::Ok().json(json!({"status": "not_logged_in"})),
+        Ok(SessionCheck::Ended) => return logged_out(&config_settings),
+        Err(response) => return response,
+    }
+}
+
+// The outcome of check_session, the caller builds its own response
+pub(crate) enum SessionCheck {
+    LoggedIn { refreshed: bool }, // The access token is valid, refreshed first if it was about to expire
+    NotLoggedIn,
+    Ended, // The session was purged just now
+}
+
+// Refreshes the tokens when the access token is within early_refresh_skew_secs of expiry
+// Shared with /token, failures are already answered (JSON, transient failures keep the session)
+pub(crate) async fn check_session(
+    config_settings: &MainConfiguration,
+    session: &Session,
+    client_data: &OpenidClientData,
+    provider_http: &Data<ProviderHttp>,
+    handler: &str,
+) -> Result<SessionCheck, HttpResponse> {
 
     // No access token = not logged in
     let has_access_token = match session.get::<String>("access_token") {
         Ok(option) => {
             match option {
                 Some(_) => true,
-                None => return HttpResponse::Ok().json(json!({"status": "not_logged_in"})),
+                None => return Ok(SessionCheck::NotLoggedIn),
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_access_token failed", Error::fmt(error)),
+        Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "extracted_access_token failed", Error::fmt(error))),
     };
 
-    // No refresh token = not logged in
-    let refresh_token = {
-        let extracted_refresh_token = match session.get::<String>("refresh_token") {
-            Ok(option) => {
-                match option {
-                    Some(token) => token,
-                    None => return HttpResponse::Ok().json(json!({"status": "not_logged_in"})),
-                }
-            }
-            Err(error) => return Error::send(session, rurl, HANDLER, "extracted_refresh_token failed", Error::fmt(error)),
-        };
-        
-        RefreshToken::new(extracted_refresh_token)
+    // No refresh token is only possible with allow_missing_refresh_token, see below
+    let refresh_token = match session.get::<String>("refresh_token") {
+        Ok(option) => option.map(RefreshToken::new),
+        Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "extracted_refresh_token failed", Error::fmt(error))),
     };
 
     let extracted_access_token_expiry = match session.get::<i64>("token_expiry") {
         Ok(option) => {
             match option {
                 Some(expiry) => expiry, // Time when the access token will expire
-                None => return Error::send(session, rurl, HANDLER, "extracted_access_token failed", None),
+                None => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "extracted_access_token failed", None)),
             }
         }
-        Err(error) => return Error::send(session, rurl, HANDLER, "extracted_access_token_expiry failed", Error::fmt(error)),
+        Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "extracted_access_token_expiry failed", Error::fmt(error))),
     };
 
     // If access token (minus the safety buffer) is not expired, user is logged in
     // Else use the refresh token to refresh the access token
     if Utc::now().timestamp() < extracted_access_token_expiry - config_settings.early_refresh_skew_secs {
         if has_access_token {
-            debugx!("sessionstatus (1) successful");
-            return actix_web::HttpResponse::Ok().json(json!({"status": "logged_in"}));
+            debugx!("[{}] {} (1) successful", request_id(), handler);
+            return Ok(SessionCheck::LoggedIn { refreshed: false });
         }
         else { // Error because this condition should never happen
-            error!("sessionstatus (1) failed");
-            return HttpResponse::Ok().json(json!({"status": "not_logged_in"}));
+            error!("[{}] {} (1) failed", request_id(), handler);
+            return Ok(SessionCheck::NotLoggedIn);
         }
     } 
     else {
+        // Without a refresh token the session ends when the access token expires, the skew does not apply
+        let refresh_token = match refresh_token {
+            Some(token) => token,
+            None => {
+                if has_access_token && Utc::now().timestamp() < extracted_access_token_expiry {
+                    debugx!("[{}] {} (3) successful", request_id(), handler);
+                    return Ok(SessionCheck::LoggedIn { refreshed: false });
+                }
+                audit(AuditEvent::SessionPurged, session, Some("expired"));
+                publish(SessionEventKind::LoggedOut, session);
+                session.purge();
+                debugx!("[{}] {} (3) session without refresh_token expired", request_id(), handler);
+                return Ok(SessionCheck::Ended);
+            }
+        };
+
         // Use the refresh token to request a new access token
         // Depending on your token settings, a new refresh token may also be returned
         let token_response = {
-            let refresh_token_request = match client_data.exchange_refresh_token(&refresh_token) {
+            let provider = match client_data.get(config_settings).await {
+                Ok(provider) => provider,
+                Err(error) => return Err(Error::transient(handler, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error))),
+            };
+
+            let mut refresh_token_request = match provider.client.exchange_refresh_token(&refresh_token) {
                 Ok(request) => request,
-                Err(error) => return Error::send(session, rurl, HANDLER, "refresh_token_request failed", Error::fmt(error)),
+                Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::RefreshFailed, "refresh_token_request failed", Error::fmt(error))),
             };
 
-            let http_client = match ClientBuilder::new().redirect(Policy::none()).timeout(timeDuration::from_secs(10)).build() {
-                Ok(http_client) => http_client,
-                Err(error) => return Error::send(session, rurl, HANDLER, "http_client failed", Error::fmt(error)),
+            // client_secret_jwt and private_key_jwt add a signed client assertion, the audience is the token endpoint
+            let token_url = match provider.client.token_uri() {
+                Some(url) => url.as_str(),
+                None => return Err(Error::json(session.clone(), handler, ErrorCode::InternalError, "no token endpoint", None)),
+            };
+            match client_data.client_auth().extra_params(token_url) {
+                Ok(params) => {
+                    for (name, value) in params {
+                        refresh_token_request = refresh_token_request.add_extra_param(name, value);
+                    }
+                }
+                Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::InternalError, "client assertion failed", Error::fmt(error))),
+            }
+
+            // A DPoP-bound session refreshes with the same key (RFC 9449 section 5)
+            let dpop_key = match session.get::<String>(DPOP_KEY_SESSION) {
+                Ok(Some(value)) => {
+                    match DpopKey::from_session_value(&value) {
+                        Ok(key) => Some(key),
+                        Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "dpop_key failed", Error::fmt(error))),
+                    }
+                }
+                Ok(None) => None,
+                Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "dpop_key failed", Error::fmt(error))),
             };
 
-            match refresh_token_request.request_async(&http_client).await {
+            let token_result = match &dpop_key {
+                Some(key) => traced("token refresh", SpanKind::Client, refresh_token_request.request_async(&DpopHttp::new(provider_http.get_ref(), key))).await,
+                None => traced("token refresh", SpanKind::Client, refresh_token_request.request_async(provider_http.get_ref())).await,
+            };
+
+            match token_result {
                 Ok(response) => response,
-                Err(error) => return Error::send(session, rurl, HANDLER, "status token_response failed", Error::fmt(error)),
+                Err(error) if is_transient_token_error(&error) => {
+                    // The refresh token was not rejected, keep the session so the next poll can retry
+                    return Err(Error::transient(handler, ErrorCode::ProviderUnavailable, "status token_response failed, session kept", Error::fmt(error)));
+                }
+                Err(RequestTokenError::ServerResponse(response)) if config_settings.silent_reauth && *response.error() == CoreErrorResponseType::InvalidGrant => {
+                    // The provider rejected the refresh token (expired, revoked), its SSO session may still be valid
+                    // Only for invalid_grant, a client or scope error would fail the silent login the same way, over and over
+                    warn!("[{}] ({}) refresh rejected, silent re-authentication offered: {:?}", request_id(), handler, response.error());
+                    audit(AuditEvent::SessionPurged, session, Some("refresh_rejected"));
+                    publish(SessionEventKind::LoggedOut, session);
+                    session.purge();
+                    return Ok(SessionCheck::Ended);
+                }
+                Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::RefreshFailed, "status token_response failed", Error::fmt(error))),
             }
         };
 
@@ -111,45 +193,129 @@ pub(crate) async fn sessionstatus_handler(
             if let Some(expires_in) = token_response.expires_in() { // "expires_in" represents the access token lifetime only, the refresh token's lifetime is managed by the provider and not returned here
                 let expiry = match chronoDuration::from_std(expires_in) {
                     Ok(time) => time,
-                    Err(error) => return Error::send(session, rurl, HANDLER, "expiry failed", Error::fmt(error)),
+                    Err(error) => return Err(Error::json(session.clone(), handler, ErrorCode::InvalidToken, "expiry failed", Error::fmt(error))),
                 };
                 let new_expiry = (Utc::now() + expiry).timestamp();
         
                 if let Err(error) = session.insert("token_expiry", new_expiry) {
-                    return Error::send(session, rurl, HANDLER, "status failed to store token_expiry", Error::fmt(error));
+                    return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "status failed to store token_expiry", Error::fmt(error)));
                 }
 
                 new_expiry
             } 
             else {
-                return Error::send(session, rurl, HANDLER, "status missing expiry", None);
+                return Err(Error::json(session.clone(), handler, ErrorCode::InvalidToken, "status missing expiry", None));
             }
         };
 
         // Add new access token to session
         if let Err(error) = session.insert("access_token", token_response.access_token().secret()) {
-            return Error::send(session, rurl, HANDLER, "status failed to store access_token", Error::fmt(error));
+            return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "status failed to store access_token", Error::fmt(error)));
         }
 
+        // Tokens exchanged for the previous access token, the refreshed token may carry different rights
+        session.remove(EXCHANGED_TOKENS_SESSION);
+
         // If a new refresh token was returned, add it to the session
         if let Some(rtoken) = token_response.refresh_token() {
             if let Err(error) = session.insert("refresh_token", rtoken.secret()) {
-                return Error::send(session, rurl, HANDLER, "status failed to store refresh_token", Error::fmt(error));
+                return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "status failed to store refresh_token", Error::fmt(error)));
             }      
         }
 
+        let scopes = token_response.scopes().map(|scopes| scopes.iter().map(|scope| scope.to_string()).collect());
+        if let Err(error) = store_scopes(session, scopes) {
+            return Err(Error::json(session.clone(), handler, ErrorCode::SessionError, "status failed to store scopes", Error::fmt(error)));
+        }
+
+        audit(AuditEvent::Refresh, session, None);
+        publish(SessionEventKind::Refreshed, session);
+
         // The access token was successfully refreshed
         // If access token (minus the safety buffer) is not expired, user is logged in
         if Utc::now().timestamp() < new_expiration - config_settings.early_refresh_skew_secs {
-            debugx!("sessionstatus (2) successful");
-            return HttpResponse::Ok().json(json!({"status": "logged_in"}));
+            debugx!("[{}] {} (2) successful", request_id(), handler);
+            return Ok(SessionCheck::LoggedIn { refreshed: true });
         }
         else {
             // After the refresh flow, the access token is still expired 
-            error!("sessionstatus (2) failed");
-            return HttpResponse::Ok().json(json!({"status": "not_logged_in"}));
+            error!("[{}] {} (2) failed", request_id(), handler);
+            return Ok(SessionCheck::NotLoggedIn);
+        }
+    }
+}
+
+// The session has ended, with silent_reauth the frontend may try /login?prompt=none before showing a login button
+fn logged_out(config_settings: &MainConfiguration) -> HttpResponse {
+    if config_settings.silent_reauth {
+        return HttpResponse::Ok().json(json!({"status": "not_logged_in", "silent_reauth": true}));
+    }
+    return HttpResponse::Ok().json(json!({"status": "not_logged_in"}));
+}
+
+// Also reports the assurance of the last login (acr, amr, auth_time), so the frontend can decide when to call /stepup
+// sessionstatus_fields adds expiry and identity fields, so the frontend can schedule its polling and warn before the session ends
+fn logged_in(session: &Session, config_settings: &MainConfiguration, refreshed: bool) -> HttpResponse {
+    let acr = match session.get::<Option<String>>("acr") {
+        Ok(Some(value)) => value,
+        _ => None,
+    };
+    let amr = match session.get::<Vec<String>>("amr") {
+        Ok(Some(value)) => value,
+        _ => Vec::new(),
+    };
+    let auth_time = match session.get::<Option<i64>>("auth_time") {
+        Ok(Some(value)) => value,
+        _ => None,
+    };
+    let mut body = json!({"status": "logged_in", "acr": acr, "amr": amr, "auth_time": auth_time});
+
+    let token_expiry = match session.get::<i64>("token_expiry") {
+        Ok(value) => value,
+        Err(_) => None,
+    };
+    for field in &config_settings.sessionstatus_fields {
+        let value = match field {
+            StatusField::TokenExpiry => json!(token_expiry),
+            StatusField::SessionExpiry => {
+                match session.get::<i64>("session_expiry") {
+                    Ok(value) => json!(value),
+                    Err(_) => Value::Null,
+                }
+            }
+            StatusField::RefreshIn => {
+                // Without a refresh token there is no refresh, the session ends at token_expiry
+                let has_refresh_token = matches!(session.get::<String>("refresh_token"), Ok(Some(_)));
+                match token_expiry {
+                    Some(expiry) if has_refresh_token => json!((expiry - config_settings.early_refresh_skew_secs - Utc::now().timestamp()).max(0)),
+                    _ => Value::Null,
+                }
+            }
+            StatusField::Refreshed => json!(refreshed),
+            StatusField::UserId => {
+                match session.get::<String>("user_id") {
+                    Ok(value) => json!(value),
+                    Err(_) => Value::Null,
+                }
+            }
+            StatusField::Scopes => {
+                match session.get::<Vec<String>>("scopes") {
+                    Ok(value) => json!(value),
+                    Err(_) => Value::Null,
+                }
+            }
+        };
+        body[field.name()] = value;
+    }
+
+    // Only the web client can read this answer (CORS), a forged request to /token cannot know the value
+    if config_settings.token_route {
+        if let Ok(Some(csrf_token)) = session.get::<String>("csrf_token") {
+            body["csrf_token"] = json!(csrf_token);
         }
     }
+
+    return HttpResponse::Ok().json(body);
 }
 
 ////////// END OF FILE //////////
\ No newline at end of file
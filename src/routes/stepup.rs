@@ -0,0 +1,57 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::OpenidClientData;
+use crate::routes::login::{start_authorization, AuthorizationOptions};
+use crate::tools::error::{Error, ErrorCode};
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::settings::MainConfiguration;
+use std::collections::HashMap;
+use std::time::Duration;
+use actix_web::HttpResponse;
+use actix_web::web::{Query, Data};
+use actix_session::Session;
+use openidconnect::core::CoreAuthPrompt;
+
+const HANDLER: &str = "stepup"; // Used for error logging
+
+// /stepup?acr=... sends the user through the login flow again with acr_values, prompt=login and max_age=0
+// callback_handler then requires one of the requested acr values and a fresh auth_time
+pub(crate) async fn stepup_handler(
+    config_settings: Data<MainConfiguration>, 
+    session: Session, 
+    client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    query: Query<HashMap<String, String>>, 
+) -> HttpResponse {
+
+    let acr_values = match query.get("acr") {
+        Some(acr) => acr.trim().to_string(),
+        None => return Error::redirect(&config_settings.error_redirect_url, HANDLER, ErrorCode::InvalidRequest, "no acr in query", None),
+    };
+    if acr_values.is_empty() || acr_values.len() > 256 {
+        return Error::redirect(&config_settings.error_redirect_url, HANDLER, ErrorCode::InvalidRequest, "acr is not valid", None);
+    }
+
+    let options = AuthorizationOptions {
+        acr_values: Some(acr_values),
+        prompt: Some(CoreAuthPrompt::Login),
+        max_age: Some(Duration::from_secs(0)),
+        keep_session_on_error: true,
+    };
+    return start_authorization(config_settings, session, client_data, provider_http, HANDLER, options).await;
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
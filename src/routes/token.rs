@@ -0,0 +1,273 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::OpenidClientData;
+use crate::tools::error::{Error, ErrorCode};
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::dpop::DPOP_KEY_SESSION;
+use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::request_id;
+use crate::tools::tokenexchange::{exchanged_token, TokenExchangeError};
+use crate::routes::sessionstatus::{check_session, SessionCheck};
+use better_logger::logger::debugx;
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header::HeaderValue;
+use actix_web::web::{Data, Query};
+use actix_session::Session;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use std::collections::HashMap;
+use serde_json::{json, Value};
+
+const HANDLER: &str = "token"; // Used for error logging
+
+// Hands the current access token to a trusted frontend that must call an API directly (token_route)
+// Refreshed first like /sessionstatus, refused unless origin, CSRF token and the token policy allow it
+pub(crate) async fn token_handler(
+    config_settings: Data<MainConfiguration>,
+    session: Session,
+    client_data: OpenidClientData,
+    provider_http: Data<ProviderHttp>,
+    query: Query<HashMap<String, String>>,
+    req: HttpRequest,
+) -> HttpResponse {
+
+    // Browsers always send Origin on a cross-origin fetch, it cannot be set by a page
+    let origin = match req.headers().get("Origin") {
+        Some(value) => {
+            match value.to_str() {
+                Ok(origin) => origin,
+                Err(_) => return Error::reject(HANDLER, ErrorCode::Forbidden, "unreadable Origin", None),
+            }
+        }
+        None => return Error::reject(HANDLER, ErrorCode::Forbidden, "no Origin", None),
+    };
+    if !origin_allowed(&config_settings.token_route_allowed_origins, origin) {
+        return Error::reject(HANDLER, ErrorCode::Forbidden, "origin not allowed", Error::fmt(origin));
+    }
+
+    // The value is only readable by the web client (/sessionstatus), a cross-site request cannot send it
+    let expected_csrf_token = match session.get::<String>("csrf_token") {
+        Ok(Some(token)) => token,
+        Ok(None) => return Error::reject(HANDLER, ErrorCode::NotAuthenticated, "no csrf_token in session", None),
+        Err(error) => return Error::reject(HANDLER, ErrorCode::SessionError, "csrf_token failed", Error::fmt(error)),
+    };
+    if let Err(msg) = check_csrf_token(req.headers().get("X-CSRF-Token"), &expected_csrf_token) {
+        return Error::reject(HANDLER, ErrorCode::Forbidden, msg, None);
+    }
+
+    match check_session(&config_settings, &session, &client_data, &provider_http, HANDLER).await {
+        Ok(SessionCheck::LoggedIn { .. }) => {}
+        Ok(SessionCheck::NotLoggedIn) | Ok(SessionCheck::Ended) => return Error::reject(HANDLER, ErrorCode::NotAuthenticated, "not logged in", None),
+        Err(response) => return response,
+    }
+
+    // A DPoP-bound token is useless without the key, and the key never leaves GOBSG
+    if let Ok(Some(_)) = session.get::<String>(DPOP_KEY_SESSION) {
+        return Error::reject(HANDLER, ErrorCode::Forbidden, "the access token is DPoP-bound", None);
+    }
+
+    let access_token = match session.get::<String>("access_token") {
+        Ok(Some(token)) => token,
+        Ok(None) => return Error::reject(HANDLER, ErrorCode::NotAuthenticated, "no access_token", None),
+        Err(error) => return Error::reject(HANDLER, ErrorCode::SessionError, "access_token failed", Error::fmt(error)),
+    };
+    let token_expiry = match session.get::<i64>("token_expiry") {
+        Ok(Some(expiry)) => expiry,
+        Ok(None) => return Error::reject(HANDLER, ErrorCode::NotAuthenticated, "no token_expiry", None),
+        Err(error) => return Error::reject(HANDLER, ErrorCode::SessionError, "token_expiry failed", Error::fmt(error)),
+    };
+
+    // ?audience= asks for a token restricted to one upstream, exchanged at the token endpoint (RFC 8693)
+    if let Some(audience) = query.get("audience") {
+        if !config_settings.token_exchange_audiences.contains(audience) {
+            return Error::reject(HANDLER, ErrorCode::Forbidden, "audience not in token_exchange_audiences", Error::fmt(audience));
+        }
+        let provider = match client_data.get(&config_settings).await {
+            Ok(provider) => provider,
+            Err(error) => return Error::transient(HANDLER, ErrorCode::ProviderUnavailable, "provider not ready", Error::fmt(error)),
+        };
+        let token_url = match provider.client.token_uri() {
+            Some(url) => url.as_str(),
+            None => return Error::reject(HANDLER, ErrorCode::InternalError, "no token endpoint", None),
+        };
+        let exchanged = match exchanged_token(&session, &provider_http, client_data.client_auth(), token_url, &access_token, audience, config_settings.early_refresh_skew_secs).await {
+            Ok(token) => token,
+            Err(TokenExchangeError::Transient(error)) => return Error::transient(HANDLER, ErrorCode::ProviderUnavailable, "token exchange failed", Error::fmt(error)),
+            Err(TokenExchangeError::Rejected(error)) => return Error::reject(HANDLER, ErrorCode::TokenExchangeFailed, "token exchange rejected", Error::fmt(error)),
+        };
+
+        let mut body = json!({
+            "access_token": exchanged.access_token,
+            "token_type": exchanged.token_type,
+            "issued_token_type": exchanged.issued_token_type,
+            "audience": audience,
+        });
+        // The provider did not say when the token expires, reporting 0 would tell the frontend it already has
+        if let Some(expires_at) = exchanged.expires_at {
+            body["expires_at"] = json!(expires_at);
+            body["expires_in"] = json!((expires_at - Utc::now().timestamp()).max(0));
+        }
+
+        debugx!("[{}] token exchanged for {}", request_id(), audience);
+        return HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .insert_header(("Pragma", "no-cache"))
+        .json(body);
+    }
+
+    // With only token_exchange_audiences configured, the session's own token is never handed out
+    if config_settings.token_route_allowed_scopes.is_empty() && config_settings.token_route_allowed_audiences.is_empty() {
+        return Error::reject(HANDLER, ErrorCode::Forbidden, "only exchanged tokens are allowed, no audience requested", None);
+    }
+
+    // Every granted scope must be allowed, a token with more rights than the frontend needs is not handed out
+    if !config_settings.token_route_allowed_scopes.is_empty() {
+        let scopes = match session.get::<Vec<String>>("scopes") {
+            Ok(Some(scopes)) => scopes,
+            _ => return Error::reject(HANDLER, ErrorCode::Forbidden, "granted scopes unknown", None),
+        };
+        if let Some(scope) = first_not_allowed(&scopes, &config_settings.token_route_allowed_scopes) {
+            return Error::reject(HANDLER, ErrorCode::Forbidden, "scope not allowed", Error::fmt(scope));
+        }
+    }
+
+    // Every audience must be allowed, an opaque (non-JWT) access token has no readable audience
+    if !config_settings.token_route_allowed_audiences.is_empty() {
+        let audiences = match jwt_audiences(&access_token) {
+            Some(audiences) => audiences,
+            None => return Error::reject(HANDLER, ErrorCode::Forbidden, "access token has no readable aud", None),
+        };
+        if audiences.is_empty() {
+            return Error::reject(HANDLER, ErrorCode::Forbidden, "access token has no aud", None);
+        }
+        if let Some(audience) = first_not_allowed(&audiences, &config_settings.token_route_allowed_audiences) {
+            return Error::reject(HANDLER, ErrorCode::Forbidden, "audience not allowed", Error::fmt(audience));
+        }
+    }
+
+    debugx!("[{}] token successful", request_id());
+    return HttpResponse::Ok()
+    .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+    .insert_header(("Pragma", "no-cache"))
+    .json(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_at": token_expiry,
+        "expires_in": (token_expiry - Utc::now().timestamp()).max(0),
+    }));
+}
+
+// The aud claim of a JWT access token, not verified: the token came from the provider's token endpoint
+fn jwt_audiences(access_token: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = access_token.split('.').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let payload = match URL_SAFE_NO_PAD.decode(parts[1]) {
+        Ok(bytes) => bytes,
+        Err(_) => return None,
+    };
+    let claims: Value = match serde_json::from_slice(&payload) {
+        Ok(claims) => claims,
+        Err(_) => return None,
+    };
+    match claims.get("aud") {
+        Some(Value::String(audience)) => return Some(vec![audience.clone()]),
+        Some(Value::Array(audiences)) => return Some(audiences.iter().filter_map(|audience| audience.as_str().map(|text| text.to_string())).collect()),
+        _ => return Some(Vec::new()),
+    }
+}
+
+// Configured origins may end with a slash, the Origin header never does
+fn origin_allowed(allowed_origins: &[String], origin: &str) -> bool {
+    return allowed_origins.iter().any(|allowed| allowed.trim_end_matches('/') == origin);
+}
+
+fn check_csrf_token(header: Option<&HeaderValue>, expected: &str) -> Result<(), &'static str> {
+    match header {
+        Some(value) => {
+            if !constant_time_eq(value.as_bytes(), expected.as_bytes()) {
+                return Err("X-CSRF-Token mismatch");
+            }
+            return Ok(());
+        }
+        None => return Err("no X-CSRF-Token"),
+    }
+}
+
+// The first granted scope or audience that is not in the allowlist
+fn first_not_allowed<'a>(values: &'a [String], allowed: &[String]) -> Option<&'a String> {
+    return values.iter().find(|value| !allowed.contains(value));
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    return left.iter().zip(right.iter()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: Value) -> String {
+        return format!("{}.{}.signature", URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256"}"#), URL_SAFE_NO_PAD.encode(claims.to_string()));
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        return values.iter().map(|value| value.to_string()).collect();
+    }
+
+    #[test]
+    fn csrf_token_must_be_sent_and_match() {
+        let expected = "k3Yx0-csrf";
+        assert_eq!(check_csrf_token(Some(&HeaderValue::from_static("k3Yx0-csrf")), expected), Ok(()));
+        assert_eq!(check_csrf_token(Some(&HeaderValue::from_static("k3Yx0-csrX")), expected), Err("X-CSRF-Token mismatch"));
+        assert_eq!(check_csrf_token(Some(&HeaderValue::from_static("k3Yx0")), expected), Err("X-CSRF-Token mismatch"));
+        assert_eq!(check_csrf_token(Some(&HeaderValue::from_static("")), expected), Err("X-CSRF-Token mismatch"));
+        assert_eq!(check_csrf_token(None, expected), Err("no X-CSRF-Token"));
+    }
+
+    #[test]
+    fn audiences_are_read_from_the_jwt_payload() {
+        assert_eq!(jwt_audiences(&jwt(json!({"sub": "user-1"}))), Some(Vec::new()));
+        assert_eq!(jwt_audiences(&jwt(json!({"aud": "api"}))), Some(strings(&["api"])));
+        assert_eq!(jwt_audiences(&jwt(json!({"aud": ["api", "billing"]}))), Some(strings(&["api", "billing"])));
+        assert_eq!(jwt_audiences("opaque-access-token"), None);
+    }
+
+    #[test]
+    fn every_scope_and_audience_must_be_allowed() {
+        let allowed = strings(&["openid", "profile", "api.read"]);
+        assert_eq!(first_not_allowed(&strings(&["openid", "api.read"]), &allowed), None);
+        assert_eq!(first_not_allowed(&strings(&["openid", "api.write", "profile"]), &allowed), Some(&"api.write".to_string()));
+        assert_eq!(first_not_allowed(&strings(&["billing"]), &strings(&["api"])), Some(&"billing".to_string()));
+    }
+
+    #[test]
+    fn origin_must_be_in_the_allowlist() {
+        let allowed = strings(&["https://app.example.com/", "http://localhost:5000"]);
+        assert!(origin_allowed(&allowed, "https://app.example.com"));
+        assert!(origin_allowed(&allowed, "http://localhost:5000"));
+        assert!(!origin_allowed(&allowed, "https://evil.example.com"));
+        assert!(!origin_allowed(&allowed, "http://app.example.com"));
+        assert!(!origin_allowed(&allowed, "null"));
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
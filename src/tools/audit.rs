@@ -0,0 +1,227 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::current_request_context;
+use better_logger::logger::error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration as timeDuration;
+use actix_session::Session;
+use openidconnect::reqwest::Client;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use ring::digest::{digest, SHA256};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+// Random id kept in the session, only its hash is written to audit events
+const AUDIT_SESSION_ID: &str = "audit_session_id";
+
+// Events waiting per sink, beyond this they are dropped and counted instead of growing memory
+const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+// Set once at startup, empty = audit events are dropped
+static AUDIT_SINKS: OnceLock<Vec<AuditSink>> = OnceLock::new();
+
+// Each sink has its own queue, a slow endpoint never delays the file
+struct AuditSink {
+    name: &'static str,
+    sender: Sender<String>,
+    dropped: Arc<AtomicU64>, // Reset and logged by the sink once it catches up
+}
+
+impl AuditSink {
+    fn new(name: &'static str) -> (AuditSink, Receiver<String>, Arc<AtomicU64>) {
+        let (sender, receiver) = channel::<String>(AUDIT_QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        return (AuditSink { name: name, sender: sender, dropped: dropped.clone() }, receiver, dropped);
+    }
+
+    fn send(&self, line: String) {
+        match self.sender.try_send(line) {
+            Ok(_) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Closed(_)) => error!("(audit) {} sink stopped, event dropped", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AuditEvent {
+    LoginSuccess,
+    LoginFailure,
+    Refresh,
+    Logout,
+    SessionPurged,
+}
+
+impl AuditEvent {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::LoginSuccess => return "login_success",
+            AuditEvent::LoginFailure => return "login_failure",
+            AuditEvent::Refresh => return "refresh",
+            AuditEvent::Logout => return "logout",
+            AuditEvent::SessionPurged => return "session_purged",
+        }
+    }
+}
+
+// Events are written in the background, a slow file system or endpoint never holds up a request
+pub(crate) fn spawn_audit_task(config_settings: &MainConfiguration) -> Result<(), String> {
+    let file = match &config_settings.audit_log_path {
+        Some(path) => {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(file),
+                Err(error) => return Err(format!("audit_log_path {:?}: {:?}", path, error)),
+            }
+        }
+        None => None,
+    };
+    let endpoint = match &config_settings.audit_endpoint_url {
+        Some(url) => {
+            let client = match Client::builder().timeout(timeDuration::from_secs(5)).build() {
+                Ok(client) => client,
+                Err(error) => return Err(format!("audit client: {:?}", error)),
+            };
+            Some((client, url.clone()))
+        }
+        None => None,
+    };
+    if file.is_none() && endpoint.is_none() {
+        return Ok(());
+    }
+
+    let mut sinks = Vec::new();
+
+    // std::fs on its own thread, a slow disk never blocks the async workers
+    if let Some(mut file) = file {
+        let (sink, mut receiver, dropped) = AuditSink::new("audit_log_path");
+        sinks.push(sink);
+        let spawned = thread::Builder::new().name("gobsg-audit-file".to_string()).spawn(move || {
+            while let Some(line) = receiver.blocking_recv() {
+                report_dropped("audit_log_path", &dropped);
+                write_line(&mut file, &line);
+            }
+        });
+        if let Err(error) = spawned {
+            return Err(format!("audit file thread: {:?}", error));
+        }
+    }
+
+    if let Some((client, url)) = endpoint {
+        let (sink, mut receiver, dropped) = AuditSink::new("audit_endpoint_url");
+        sinks.push(sink);
+        tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                report_dropped("audit_endpoint_url", &dropped);
+                let result = client.post(url.as_str()).header("Content-Type", "application/json").body(line).send().await;
+                match result {
+                    Ok(response) if !response.status().is_success() => error!("(audit) endpoint returned {}", response.status()),
+                    Ok(_) => {}
+                    Err(error) => error!("(audit) endpoint failed: {:?}", error),
+                }
+            }
+        });
+    }
+
+    if AUDIT_SINKS.set(sinks).is_err() {
+        return Err("audit task already started".to_string());
+    }
+    return Ok(());
+}
+
+// Records an event for the session, call before the session is purged so the user is still known
+// user_id and the session hash are null for a session that never logged in
+pub(crate) fn audit(event: AuditEvent, session: &Session, reason: Option<&str>) {
+    let sinks = match AUDIT_SINKS.get() {
+        Some(sinks) => sinks,
+        None => return,
+    };
+
+    let user_id = match session.get::<String>("user_id") {
+        Ok(value) => value,
+        Err(_) => None,
+    };
+    // Anonymous requests (/details, /sessionstatus without a login) have no session worth recording
+    if event == AuditEvent::SessionPurged && user_id.is_none() {
+        return;
+    }
+    let session_id_hash = session_id_hash(session);
+    let (request_id, ip, forwarded_for, user_agent) = match current_request_context() {
+        Some(context) => (Some(context.request_id), context.ip, context.forwarded_for, context.user_agent),
+        None => (None, None, None, None),
+    };
+
+    let line = json!({
+        "timestamp": Utc::now().to_rfc3339(),
+        "event": event.as_str(),
+        "reason": reason,
+        "user_id": user_id,
+        "session_id_hash": session_id_hash,
+        "ip": ip,
+        "forwarded_for": forwarded_for,
+        "user_agent": user_agent,
+        "request_id": request_id,
+    }).to_string();
+
+    for sink in sinks {
+        sink.send(line.clone());
+    }
+}
+
+// Identifies a session in audit events and session events without revealing anything usable
+pub(crate) fn session_id_hash(session: &Session) -> Option<String> {
+    match session.get::<String>(AUDIT_SESSION_ID) {
+        Ok(Some(id)) => return Some(hex::encode(digest(&SHA256, id.as_bytes()).as_ref())),
+        _ => return None,
+    }
+}
+
+// Gives a newly logged in session its audit id, a step-up login keeps the existing one
+pub(crate) fn start_audit_session(session: &Session) -> Result<(), String> {
+    match session.get::<String>(AUDIT_SESSION_ID) {
+        Ok(Some(_)) => return Ok(()),
+        Ok(None) => {}
+        Err(error) => return Err(format!("{:?}", error)),
+    }
+    match session.insert(AUDIT_SESSION_ID, Uuid::new_v4().to_string()) {
+        Ok(_) => return Ok(()),
+        Err(error) => return Err(format!("{:?}", error)),
+    }
+}
+
+// Logged once the sink has room again, so a burst does not also flood the logs
+fn report_dropped(sink_name: &str, dropped: &AtomicU64) {
+    let count = dropped.swap(0, Ordering::Relaxed);
+    if count > 0 {
+        error!("(audit) {} could not keep up, {} event(s) dropped", sink_name, count);
+    }
+}
+
+fn write_line(file: &mut File, line: &str) {
+    if let Err(error) = writeln!(file, "{}", line) {
+        error!("(audit) audit_log_path write failed: {:?}", error);
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,56 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use openidconnect::core::CoreIdTokenClaims;
+use serde::Deserialize;
+
+// id token claims that can provide the username, see username_claims
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub(crate) enum UsernameClaim {
+    #[serde(rename = "preferred_username")]
+    PreferredUsername,
+    #[serde(rename = "email")]
+    Email,
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "nickname")]
+    Nickname,
+    #[serde(rename = "given_name")]
+    GivenName,
+    #[serde(rename = "sub")]
+    Subject,
+}
+
+// The first claim of the chain that the id token carries (and is not empty)
+pub(crate) fn username_from_claims(chain: &[UsernameClaim], claims: &CoreIdTokenClaims) -> Option<String> {
+    for claim in chain {
+        let value = match claim {
+            UsernameClaim::PreferredUsername => claims.preferred_username().map(|name| name.as_str().to_string()),
+            UsernameClaim::Email => claims.email().map(|email| email.as_str().to_string()),
+            UsernameClaim::Name => claims.name().and_then(|name| name.get(None)).map(|name| name.as_str().to_string()),
+            UsernameClaim::Nickname => claims.nickname().and_then(|name| name.get(None)).map(|name| name.as_str().to_string()),
+            UsernameClaim::GivenName => claims.given_name().and_then(|name| name.get(None)).map(|name| name.as_str().to_string()),
+            UsernameClaim::Subject => Some(claims.subject().as_str().to_string()),
+        };
+        if let Some(username) = value {
+            if !username.is_empty() {
+                return Some(username);
+            }
+        }
+    }
+    return None;
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,341 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use std::fs::read;
+use std::str::FromStr;
+use openidconnect::{AuthType, ClientSecret};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use chrono::Utc;
+use uuid::Uuid;
+use url::form_urlencoded::byte_serialize;
+
+pub(crate) const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+
+// How GOBSG authenticates itself to the provider's token endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TokenEndpointAuthMethod {
+    ClientSecretBasic,
+    ClientSecretPost,
+    ClientSecretJwt,
+    PrivateKeyJwt,
+    TlsClientAuth, // RFC 8705, PKI certificate
+    SelfSignedTlsClientAuth, // RFC 8705, self-signed certificate registered with the provider
+}
+
+impl FromStr for TokenEndpointAuthMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "client_secret_basic" => return Ok(TokenEndpointAuthMethod::ClientSecretBasic),
+            "client_secret_post" => return Ok(TokenEndpointAuthMethod::ClientSecretPost),
+            "client_secret_jwt" => return Ok(TokenEndpointAuthMethod::ClientSecretJwt),
+            "private_key_jwt" => return Ok(TokenEndpointAuthMethod::PrivateKeyJwt),
+            "tls_client_auth" => return Ok(TokenEndpointAuthMethod::TlsClientAuth),
+            "self_signed_tls_client_auth" => return Ok(TokenEndpointAuthMethod::SelfSignedTlsClientAuth),
+            other => return Err(format!("unsupported token_endpoint_auth_method: {:?}", other)),
+        }
+    }
+}
+
+// RFC 7523 client assertion claims
+#[derive(Serialize)]
+struct ClientAssertionClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    jti: String,
+    iat: i64,
+    exp: i64,
+}
+
+// Client authentication for a request GOBSG builds itself (not through openidconnect)
+pub(crate) struct FormCredentials {
+    pub(crate) basic: Option<(String, String)>, // Already form-urlencoded (RFC 6749 section 2.3.1)
+    pub(crate) params: Vec<(&'static str, String)>,
+}
+
+// Built once at startup, the signing key is read from disk only once
+pub(crate) struct ClientAuthentication {
+    method: TokenEndpointAuthMethod,
+    client_id: String,
+    client_secret: String, // Empty for the methods that do not use it
+    signing: Option<(EncodingKey, Header)>, // Only for client_secret_jwt and private_key_jwt
+}
+
+impl ClientAuthentication {
+    pub(crate) fn new(config_settings: &MainConfiguration) -> Result<ClientAuthentication, String> {
+        let method = TokenEndpointAuthMethod::from_str(config_settings.token_endpoint_auth_method.as_str())?;
+        let client_secret = required_client_secret(method, config_settings.client_secret.as_deref())?;
+
+        let signing = match method {
+            TokenEndpointAuthMethod::ClientSecretBasic | TokenEndpointAuthMethod::ClientSecretPost => None,
+            TokenEndpointAuthMethod::TlsClientAuth | TokenEndpointAuthMethod::SelfSignedTlsClientAuth => None, // The TLS handshake authenticates
+            TokenEndpointAuthMethod::ClientSecretJwt => {
+                let algorithm = parse_algorithm(config_settings.client_assertion_alg.as_deref(), Algorithm::HS256)?;
+                if !matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+                    return Err(format!("client_secret_jwt requires an HMAC client_assertion_alg, got {:?}", algorithm));
+                }
+                let key = EncodingKey::from_secret(client_secret.as_bytes());
+                Some((key, Header::new(algorithm)))
+            }
+            TokenEndpointAuthMethod::PrivateKeyJwt => {
+                let algorithm = parse_algorithm(config_settings.client_assertion_alg.as_deref(), Algorithm::RS256)?;
+                let key_path = match &config_settings.client_assertion_key_path {
+                    Some(path) => path,
+                    None => return Err("private_key_jwt requires client_assertion_key_path".to_string()),
+                };
+                let key = load_signing_key(key_path, algorithm)?;
+                let mut header = Header::new(algorithm);
+                header.kid = config_settings.client_assertion_key_id.clone();
+                Some((key, header))
+            }
+        };
+
+        return Ok(ClientAuthentication {
+            method: method,
+            client_id: config_settings.client.clone(),
+            client_secret: client_secret,
+            signing: signing,
+        });
+    }
+
+    // The JWT and TLS methods never send the secret itself
+    pub(crate) fn client_secret(&self) -> Option<ClientSecret> {
+        match self.method {
+            TokenEndpointAuthMethod::ClientSecretBasic | TokenEndpointAuthMethod::ClientSecretPost => return Some(ClientSecret::new(self.client_secret.clone())),
+            _ => return None,
+        }
+    }
+
+    pub(crate) fn requires_client_certificate(&self) -> bool {
+        return matches!(self.method, TokenEndpointAuthMethod::TlsClientAuth | TokenEndpointAuthMethod::SelfSignedTlsClientAuth);
+    }
+
+    pub(crate) fn auth_type(&self) -> AuthType {
+        match self.method {
+            TokenEndpointAuthMethod::ClientSecretBasic => return AuthType::BasicAuth,
+            _ => return AuthType::RequestBody, // client_id goes in the request body
+        }
+    }
+
+    // Extra form parameters for a request to the token endpoint (audience), empty for the secret methods
+    // A new assertion is signed for every request, assertions are single use (jti)
+    pub(crate) fn extra_params(&self, audience: &str) -> Result<Vec<(&'static str, String)>, String> {
+        let (key, header) = match &self.signing {
+            Some(signing) => signing,
+            None => return Ok(Vec::new()),
+        };
+
+        let now = Utc::now().timestamp();
+        let claims = ClientAssertionClaims {
+            iss: self.client_id.as_str(),
+            sub: self.client_id.as_str(),
+            aud: audience,
+            jti: Uuid::new_v4().to_string(),
+            iat: now,
+            exp: now + 60,
+        };
+
+        let assertion = match encode(header, &claims, key) {
+            Ok(jwt) => jwt,
+            Err(error) => return Err(format!("client assertion signing failed: {:?}", error)),
+        };
+
+        return Ok(vec![
+            ("client_assertion_type", CLIENT_ASSERTION_TYPE.to_string()),
+            ("client_assertion", assertion),
+        ]);
+    }
+
+    // Same method as the token endpoint, the audience of an assertion is the endpoint it is sent to (or the issuer)
+    pub(crate) fn form_credentials(&self, audience: &str) -> Result<FormCredentials, String> {
+        match self.method {
+            TokenEndpointAuthMethod::ClientSecretBasic => {
+                let client_id: String = byte_serialize(self.client_id.as_bytes()).collect();
+                let client_secret: String = byte_serialize(self.client_secret.as_bytes()).collect();
+                return Ok(FormCredentials { basic: Some((client_id, client_secret)), params: Vec::new() });
+            }
+            TokenEndpointAuthMethod::ClientSecretPost => {
+                return Ok(FormCredentials {
+                    basic: None,
+                    params: vec![("client_id", self.client_id.clone()), ("client_secret", self.client_secret.clone())],
+                });
+            }
+            TokenEndpointAuthMethod::ClientSecretJwt | TokenEndpointAuthMethod::PrivateKeyJwt => {
+                let mut params = vec![("client_id", self.client_id.clone())];
+                params.extend(self.extra_params(audience)?);
+                return Ok(FormCredentials { basic: None, params: params });
+            }
+            TokenEndpointAuthMethod::TlsClientAuth | TokenEndpointAuthMethod::SelfSignedTlsClientAuth => {
+                return Ok(FormCredentials { basic: None, params: vec![("client_id", self.client_id.clone())] });
+            }
+        }
+    }
+}
+
+// Only the secret methods use client_secret, an empty value counts as not set
+fn required_client_secret(method: TokenEndpointAuthMethod, configured: Option<&str>) -> Result<String, String> {
+    let uses_secret = matches!(method, TokenEndpointAuthMethod::ClientSecretBasic | TokenEndpointAuthMethod::ClientSecretPost | TokenEndpointAuthMethod::ClientSecretJwt);
+    match configured {
+        Some(secret) if !secret.is_empty() => return Ok(secret.to_string()),
+        _ if uses_secret => return Err("client_secret_basic, client_secret_post and client_secret_jwt require client_secret".to_string()),
+        _ => return Ok(String::new()),
+    }
+}
+
+fn parse_algorithm(configured: Option<&str>, default: Algorithm) -> Result<Algorithm, String> {
+    match configured {
+        Some(alg) => {
+            match Algorithm::from_str(alg) {
+                Ok(algorithm) => return Ok(algorithm),
+                Err(error) => return Err(format!("client_assertion_alg {:?}: {:?}", alg, error)),
+            }
+        }
+        None => return Ok(default),
+    }
+}
+
+// PEM private key, RSA for RS*/PS*, EC for ES*, Ed25519 for EdDSA
+pub(crate) fn load_signing_key(key_path: &str, algorithm: Algorithm) -> Result<EncodingKey, String> {
+    let pem = match read(key_path) {
+        Ok(bytes) => bytes,
+        Err(error) => return Err(format!("read({:?}) failed: {:?}", key_path, error)),
+    };
+
+    let key = match algorithm {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => EncodingKey::from_rsa_pem(&pem),
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&pem),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+        other => return Err(format!("{:?} is not an asymmetric algorithm", other)),
+    };
+
+    match key {
+        Ok(key) => return Ok(key),
+        Err(error) => return Err(format!("{:?} is not a valid {:?} key: {:?}", key_path, algorithm, error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    use serde_json::Value;
+
+    fn client_auth(method: TokenEndpointAuthMethod) -> ClientAuthentication {
+        let signing = match method {
+            TokenEndpointAuthMethod::ClientSecretJwt => Some((EncodingKey::from_secret(b"s3cret"), Header::new(Algorithm::HS256))),
+            _ => None,
+        };
+        return ClientAuthentication {
+            method: method,
+            client_id: "my client:1".to_string(),
+            client_secret: "s3cret".to_string(),
+            signing: signing,
+        };
+    }
+
+    #[test]
+    fn auth_methods_parse() {
+        assert_eq!(TokenEndpointAuthMethod::from_str("private_key_jwt"), Ok(TokenEndpointAuthMethod::PrivateKeyJwt));
+        assert_eq!(TokenEndpointAuthMethod::from_str("client_secret_post"), Ok(TokenEndpointAuthMethod::ClientSecretPost));
+        assert!(TokenEndpointAuthMethod::from_str("none").is_err());
+    }
+
+    #[test]
+    fn client_secret_is_required_only_by_the_secret_methods() {
+        assert_eq!(required_client_secret(TokenEndpointAuthMethod::ClientSecretBasic, Some("s3cret")), Ok("s3cret".to_string()));
+        assert!(required_client_secret(TokenEndpointAuthMethod::ClientSecretBasic, None).is_err());
+        assert!(required_client_secret(TokenEndpointAuthMethod::ClientSecretPost, Some("")).is_err());
+        assert!(required_client_secret(TokenEndpointAuthMethod::ClientSecretJwt, None).is_err());
+        assert_eq!(required_client_secret(TokenEndpointAuthMethod::PrivateKeyJwt, None), Ok(String::new()));
+        assert_eq!(required_client_secret(TokenEndpointAuthMethod::TlsClientAuth, Some("")), Ok(String::new()));
+    }
+
+    #[test]
+    fn secret_methods_send_the_secret() {
+        let basic = client_auth(TokenEndpointAuthMethod::ClientSecretBasic);
+        assert_eq!(basic.client_secret().unwrap().secret(), "s3cret");
+        assert!(matches!(basic.auth_type(), AuthType::BasicAuth));
+        assert!(basic.extra_params("https://issuer.example/token").unwrap().is_empty());
+
+        let post = client_auth(TokenEndpointAuthMethod::ClientSecretPost);
+        assert_eq!(post.client_secret().unwrap().secret(), "s3cret");
+        assert!(matches!(post.auth_type(), AuthType::RequestBody));
+    }
+
+    #[test]
+    fn client_assertion_is_signed_for_the_audience() {
+        let auth = client_auth(TokenEndpointAuthMethod::ClientSecretJwt);
+        assert!(auth.client_secret().is_none());
+        let params = auth.extra_params("https://issuer.example/token").unwrap();
+        assert_eq!(params[0], ("client_assertion_type", CLIENT_ASSERTION_TYPE.to_string()));
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_audience(&["https://issuer.example/token"]);
+        let claims = decode::<Value>(&params[1].1, &DecodingKey::from_secret(b"s3cret"), &validation).unwrap().claims;
+        assert_eq!(claims["iss"], "my client:1");
+        assert_eq!(claims["sub"], "my client:1");
+        assert_eq!(claims["exp"].as_i64().unwrap() - claims["iat"].as_i64().unwrap(), 60);
+
+        // Assertions are single use
+        let second = auth.extra_params("https://issuer.example/token").unwrap();
+        assert_ne!(second[1].1, params[1].1);
+    }
+
+    #[test]
+    fn tls_methods_need_a_client_certificate() {
+        assert_eq!(TokenEndpointAuthMethod::from_str("self_signed_tls_client_auth"), Ok(TokenEndpointAuthMethod::SelfSignedTlsClientAuth));
+        let auth = client_auth(TokenEndpointAuthMethod::TlsClientAuth);
+        assert!(auth.requires_client_certificate());
+        assert!(auth.client_secret().is_none());
+        assert!(auth.extra_params("https://issuer.example/token").unwrap().is_empty());
+        assert!(!client_auth(TokenEndpointAuthMethod::ClientSecretBasic).requires_client_certificate());
+    }
+
+    #[test]
+    fn basic_credentials_are_form_urlencoded() {
+        let credentials = client_auth(TokenEndpointAuthMethod::ClientSecretBasic).form_credentials("https://issuer.example/token").unwrap();
+        assert_eq!(credentials.basic, Some(("my+client%3A1".to_string(), "s3cret".to_string())));
+        assert!(credentials.params.is_empty());
+    }
+
+    #[test]
+    fn post_credentials_go_in_the_body() {
+        let credentials = client_auth(TokenEndpointAuthMethod::ClientSecretPost).form_credentials("https://issuer.example/token").unwrap();
+        assert_eq!(credentials.basic, None);
+        assert_eq!(credentials.params, vec![("client_id", "my client:1".to_string()), ("client_secret", "s3cret".to_string())]);
+    }
+
+    #[test]
+    fn assertion_credentials_carry_the_client_id() {
+        let credentials = client_auth(TokenEndpointAuthMethod::ClientSecretJwt).form_credentials("https://issuer.example/token").unwrap();
+        assert_eq!(credentials.basic, None);
+        assert_eq!(credentials.params[0], ("client_id", "my client:1".to_string()));
+        assert_eq!(credentials.params[1], ("client_assertion_type", CLIENT_ASSERTION_TYPE.to_string()));
+        assert_eq!(credentials.params.len(), 3);
+    }
+
+    #[test]
+    fn tls_credentials_send_only_the_client_id() {
+        let credentials = client_auth(TokenEndpointAuthMethod::SelfSignedTlsClientAuth).form_credentials("https://issuer.example/token").unwrap();
+        assert_eq!(credentials.basic, None);
+        assert_eq!(credentials.params, vec![("client_id", "my client:1".to_string())]);
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
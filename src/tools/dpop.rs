@@ -0,0 +1,328 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::providerhttp::{copy_request, ProviderHttp};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use openidconnect::{AsyncHttpClient, HttpClientError, HttpRequest, HttpResponse};
+use openidconnect::core::CoreTokenType;
+use openidconnect::http::HeaderValue;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use jsonwebtoken::jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, Jwk};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use serde::Serialize;
+use chrono::Utc;
+use uuid::Uuid;
+use url::Url;
+
+pub(crate) const DPOP_KEY_SESSION: &str = "dpop_key"; // Session key of the private key, sessions are stored server side in Redis
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+const SEALING_KEY_SALT: &[u8] = b"gobsg dpop key sealing";
+
+// Set once at startup when dpop is enabled, derived from secret_cookie_hex_key
+static SEALING_KEY: OnceLock<LessSafeKey> = OnceLock::new();
+
+// Whoever can read Redis sees the tokens, the private key next to them is sealed so the tokens cannot be used from elsewhere
+pub(crate) fn init_key_sealing(cookie_key: &[u8]) -> Result<(), String> {
+    if SEALING_KEY.set(sealing_key(cookie_key)?).is_err() {
+        return Err("dpop key sealing already initialized".to_string());
+    }
+    return Ok(());
+}
+
+fn sealing_key(cookie_key: &[u8]) -> Result<LessSafeKey, String> {
+    let prk = Salt::new(HKDF_SHA256, SEALING_KEY_SALT).extract(cookie_key);
+    let info = [DPOP_KEY_SESSION.as_bytes()];
+    match prk.expand(&info, &AES_256_GCM) {
+        Ok(okm) => return Ok(LessSafeKey::new(UnboundKey::from(okm))),
+        Err(_) => return Err("dpop sealing key derivation failed".to_string()),
+    }
+}
+
+// AES-256-GCM with a random nonce, the value is base64(nonce || ciphertext || tag)
+fn seal(key: &LessSafeKey, plaintext: &[u8]) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    if SystemRandom::new().fill(&mut nonce).is_err() {
+        return Err("dpop key nonce failed".to_string());
+    }
+    let mut sealed = plaintext.to_vec();
+    if key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(DPOP_KEY_SESSION.as_bytes()), &mut sealed).is_err() {
+        return Err("dpop key sealing failed".to_string());
+    }
+
+    let mut value = nonce.to_vec();
+    value.extend(sealed);
+    return Ok(STANDARD.encode(value));
+}
+
+fn open(key: &LessSafeKey, value: &str) -> Result<Vec<u8>, String> {
+    let mut sealed = match STANDARD.decode(value) {
+        Ok(bytes) => bytes,
+        Err(error) => return Err(format!("dpop key in session is not valid: {:?}", error)),
+    };
+    if sealed.len() <= NONCE_LEN {
+        return Err("dpop key in session is too short".to_string());
+    }
+    let mut ciphertext = sealed.split_off(NONCE_LEN);
+    let nonce = match Nonce::try_assume_unique_for_key(&sealed) {
+        Ok(nonce) => nonce,
+        Err(_) => return Err("dpop key nonce is not valid".to_string()),
+    };
+    match key.open_in_place(nonce, Aad::from(DPOP_KEY_SESSION.as_bytes()), &mut ciphertext) {
+        Ok(plaintext) => return Ok(plaintext.to_vec()),
+        Err(_) => return Err("dpop key in session failed to open (tampered or another secret_cookie_hex_key)".to_string()),
+    }
+}
+
+// RFC 9449 section 4.2
+#[derive(Serialize)]
+struct DpopProofClaims<'a> {
+    jti: String,
+    htm: &'a str,
+    htu: String,
+    iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a str>,
+}
+
+// Per-session DPoP key (P-256, ES256), created at callback and kept for the lifetime of the session
+pub(crate) struct DpopKey {
+    pkcs8: Vec<u8>,
+    jwk: Jwk,
+}
+
+impl DpopKey {
+    pub(crate) fn generate() -> Result<DpopKey, String> {
+        let pkcs8 = match EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()) {
+            Ok(document) => document.as_ref().to_vec(),
+            Err(error) => return Err(format!("dpop key generation failed: {:?}", error)),
+        };
+        return DpopKey::from_pkcs8(pkcs8);
+    }
+
+    // The session stores the PKCS#8 document sealed with the key from init_key_sealing
+    pub(crate) fn from_session_value(value: &str) -> Result<DpopKey, String> {
+        match SEALING_KEY.get() {
+            Some(key) => return DpopKey::from_pkcs8(open(key, value)?),
+            None => return Err("dpop key sealing is not initialized".to_string()),
+        }
+    }
+
+    pub(crate) fn to_session_value(&self) -> Result<String, String> {
+        match SEALING_KEY.get() {
+            Some(key) => return seal(key, &self.pkcs8),
+            None => return Err("dpop key sealing is not initialized".to_string()),
+        }
+    }
+
+    fn from_pkcs8(pkcs8: Vec<u8>) -> Result<DpopKey, String> {
+        let key_pair = match EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &SystemRandom::new()) {
+            Ok(key_pair) => key_pair,
+            Err(error) => return Err(format!("dpop key is not valid: {:?}", error)),
+        };
+
+        // Uncompressed point: 0x04 || x || y
+        let public_key = key_pair.public_key().as_ref();
+        if public_key.len() != 65 {
+            return Err("dpop public key has an unexpected length".to_string());
+        }
+        let jwk = Jwk {
+            common: CommonParameters::default(),
+            algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+                y: URL_SAFE_NO_PAD.encode(&public_key[33..65]),
+            }),
+        };
+
+        return Ok(DpopKey { pkcs8: pkcs8, jwk: jwk });
+    }
+
+    // A new proof for every request, htu is the target URL without query and fragment
+    // Only token endpoint calls carry a proof, GOBSG never forwards the access token, so there is no ath claim
+    pub(crate) fn proof(&self, method: &str, url: &str, nonce: Option<&str>) -> Result<String, String> {
+        let mut htu = match Url::parse(url) {
+            Ok(url) => url,
+            Err(error) => return Err(format!("dpop htu {:?}: {:?}", url, error)),
+        };
+        htu.set_query(None);
+        htu.set_fragment(None);
+
+        let claims = DpopProofClaims {
+            jti: Uuid::new_v4().to_string(),
+            htm: method,
+            htu: htu.to_string(),
+            iat: Utc::now().timestamp(),
+            nonce: nonce,
+        };
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.typ = Some(DPOP_PROOF_TYPE.to_string());
+        header.jwk = Some(self.jwk.clone());
+
+        match encode(&header, &claims, &EncodingKey::from_ec_der(&self.pkcs8)) {
+            Ok(jwt) => return Ok(jwt),
+            Err(error) => return Err(format!("dpop proof signing failed: {:?}", error)),
+        }
+    }
+}
+
+// The provider answers with token_type DPoP when it bound the token to the key
+pub(crate) fn is_dpop_token_type(token_type: &CoreTokenType) -> bool {
+    match token_type {
+        CoreTokenType::Extension(name) => return name.eq_ignore_ascii_case("DPoP"),
+        _ => return false,
+    }
+}
+
+// Wraps the shared provider client for token endpoint calls, adds a DPoP proof to every request
+// If the provider asks for a nonce (use_dpop_nonce), the request is sent once more with the nonce
+pub(crate) struct DpopHttp<'a> {
+    http: &'a ProviderHttp,
+    key: &'a DpopKey,
+}
+
+impl<'a> DpopHttp<'a> {
+    pub(crate) fn new(http: &'a ProviderHttp, key: &'a DpopKey) -> DpopHttp<'a> {
+        return DpopHttp { http: http, key: key };
+    }
+
+    fn with_proof(&self, request: &HttpRequest, nonce: Option<&str>) -> Result<HttpRequest, HttpClientError<openidconnect::reqwest::Error>> {
+        let proof = match self.key.proof(request.method().as_str(), &request.uri().to_string(), nonce) {
+            Ok(proof) => proof,
+            Err(error) => return Err(HttpClientError::Other(error)),
+        };
+        let proof_value = match HeaderValue::from_str(&proof) {
+            Ok(value) => value,
+            Err(error) => return Err(HttpClientError::Other(format!("{:?}", error))),
+        };
+
+        let mut signed_request = match copy_request(request) {
+            Ok(copy) => copy,
+            Err(error) => return Err(HttpClientError::Http(error)),
+        };
+        signed_request.headers_mut().insert("DPoP", proof_value);
+        return Ok(signed_request);
+    }
+}
+
+impl<'c, 'a: 'c> AsyncHttpClient<'c> for DpopHttp<'a> {
+    type Error = HttpClientError<openidconnect::reqwest::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, Self::Error>> + Send + 'c>>;
+
+    fn call(&'c self, request: HttpRequest) -> Self::Future {
+        Box::pin(async move {
+            let response = self.http.call(self.with_proof(&request, None)?).await?;
+
+            // RFC 9449 section 8
+            let nonce = match response.headers().get("DPoP-Nonce").and_then(|value| value.to_str().ok()) {
+                Some(nonce) => nonce.to_string(),
+                None => return Ok(response),
+            };
+            let asks_for_nonce = response.status().is_client_error() && String::from_utf8_lossy(response.body()).contains("use_dpop_nonce");
+            if !asks_for_nonce {
+                return Ok(response);
+            }
+
+            return self.http.call(self.with_proof(&request, Some(nonce.as_str()))?).await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+    use serde_json::Value;
+
+    fn verified_claims(proof: &str) -> Value {
+        let header = decode_header(proof).unwrap();
+        assert_eq!(header.alg, Algorithm::ES256);
+        assert_eq!(header.typ.as_deref(), Some(DPOP_PROOF_TYPE));
+
+        // The proof must verify with the public key it carries
+        let key = DecodingKey::from_jwk(&header.jwk.unwrap()).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        return decode::<Value>(proof, &key, &validation).unwrap().claims;
+    }
+
+    #[test]
+    fn proof_is_signed_with_the_embedded_key() {
+        let key = DpopKey::generate().unwrap();
+        let claims = verified_claims(&key.proof("POST", "https://issuer.example/token?x=1#frag", None).unwrap());
+        assert_eq!(claims["htm"], "POST");
+        assert_eq!(claims["htu"], "https://issuer.example/token");
+        assert!(claims.get("nonce").is_none());
+        assert!(claims.get("ath").is_none());
+    }
+
+    #[test]
+    fn proof_carries_the_provider_nonce() {
+        let key = DpopKey::generate().unwrap();
+        let claims = verified_claims(&key.proof("POST", "https://issuer.example/token", Some("n-1")).unwrap());
+        assert_eq!(claims["nonce"], "n-1");
+    }
+
+    #[test]
+    fn key_survives_the_session_round_trip() {
+        init_key_sealing(&[7u8; 64]).unwrap();
+        let key = DpopKey::generate().unwrap();
+        let restored = DpopKey::from_session_value(&key.to_session_value().unwrap()).unwrap();
+        assert_eq!(restored.jwk, key.jwk);
+        assert!(DpopKey::from_session_value("not base64!").is_err());
+    }
+
+    #[test]
+    fn sealed_key_does_not_reveal_the_pkcs8_document() {
+        let sealing = sealing_key(&[1u8; 64]).unwrap();
+        let key = DpopKey::generate().unwrap();
+        let value = seal(&sealing, &key.pkcs8).unwrap();
+        let stored = STANDARD.decode(&value).unwrap();
+        assert!(!stored.windows(key.pkcs8.len()).any(|window| window == key.pkcs8.as_slice()));
+        assert!(!value.contains(&STANDARD.encode(&key.pkcs8)));
+        assert_eq!(open(&sealing, &value).unwrap(), key.pkcs8);
+
+        // A random nonce per seal
+        assert_ne!(seal(&sealing, &key.pkcs8).unwrap(), value);
+    }
+
+    #[test]
+    fn tampered_or_foreign_sealed_key_is_rejected() {
+        let sealing = sealing_key(&[1u8; 64]).unwrap();
+        let key = DpopKey::generate().unwrap();
+        let mut stored = STANDARD.decode(seal(&sealing, &key.pkcs8).unwrap()).unwrap();
+        let last = stored.len() - 1;
+        stored[last] ^= 0x01;
+        assert!(open(&sealing, &STANDARD.encode(&stored)).is_err());
+        stored[last] ^= 0x01;
+        stored[NONCE_LEN] ^= 0x01;
+        assert!(open(&sealing, &STANDARD.encode(&stored)).is_err());
+
+        let other = sealing_key(&[2u8; 64]).unwrap();
+        assert!(open(&other, &seal(&sealing, &key.pkcs8).unwrap()).is_err());
+        assert!(open(&sealing, &STANDARD.encode([0u8; NONCE_LEN])).is_err());
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
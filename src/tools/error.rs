@@ -13,10 +13,85 @@
 //!
 //! ---------------------------------------- //
 
-use better_logger::logger::error;
+use crate::tools::requestid::request_id;
+use crate::tools::audit::{audit, AuditEvent};
+use crate::tools::events::{publish, SessionEventKind};
+use better_logger::logger::{error, warn};
 use std::fmt::Debug;
+use std::error::Error as StdError;
 use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
 use actix_session::Session;
+use url::Url;
+use uuid::Uuid;
+use serde_json::json;
+use openidconnect::{RequestTokenError, StandardErrorResponse};
+use openidconnect::core::CoreErrorResponseType;
+
+// Seconds the web client should wait before retrying after a transient failure
+const RETRY_AFTER_SECS: u64 = 5;
+
+// Stable codes for the web client, appended to the error redirect as ?error=<code>&error_id=<id>
+// The detailed message is only logged, under the same error_id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ErrorCode {
+    AccessDenied, // The user cancelled or was refused at the provider
+    ProviderError, // Any other error the provider returned in the authorization response
+    ProviderUnavailable,
+    StateMismatch,
+    NoPendingLogin, // The callback does not belong to a login started in this session
+    InvalidRequest,
+    InvalidResponse, // JARM or issuer checks of the authorization response failed
+    TokenExchangeFailed,
+    RefreshFailed,
+    InvalidToken,
+    StepUpFailed,
+    NotAuthenticated,
+    Forbidden, // The request is not allowed (origin, CSRF token, token policy), the session is kept
+    SessionError,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AccessDenied => return "access_denied",
+            ErrorCode::ProviderError => return "provider_error",
+            ErrorCode::ProviderUnavailable => return "provider_unavailable",
+            ErrorCode::StateMismatch => return "state_mismatch",
+            ErrorCode::NoPendingLogin => return "no_pending_login",
+            ErrorCode::InvalidRequest => return "invalid_request",
+            ErrorCode::InvalidResponse => return "invalid_response",
+            ErrorCode::TokenExchangeFailed => return "token_exchange_failed",
+            ErrorCode::RefreshFailed => return "refresh_failed",
+            ErrorCode::InvalidToken => return "invalid_token",
+            ErrorCode::StepUpFailed => return "step_up_failed",
+            ErrorCode::NotAuthenticated => return "not_authenticated",
+            ErrorCode::Forbidden => return "forbidden",
+            ErrorCode::SessionError => return "session_error",
+            ErrorCode::InternalError => return "internal_error",
+        }
+    }
+
+    // HTTP status of a JSON error response
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::NotAuthenticated | ErrorCode::InvalidToken | ErrorCode::RefreshFailed => return StatusCode::UNAUTHORIZED,
+            ErrorCode::ProviderUnavailable | ErrorCode::TokenExchangeFailed => return StatusCode::BAD_GATEWAY,
+            ErrorCode::InvalidRequest => return StatusCode::BAD_REQUEST,
+            ErrorCode::Forbidden => return StatusCode::FORBIDDEN,
+            _ => return StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // The error parameter of an authorization response (RFC 6749 section 4.1.2.1)
+    pub(crate) fn from_oauth_error(error: &str) -> ErrorCode {
+        match error {
+            "access_denied" => return ErrorCode::AccessDenied,
+            _ => return ErrorCode::ProviderError,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Error;
@@ -26,21 +101,152 @@ impl Error {
         return Some(format!("{:?}", err));
     }
 
-    // log, purge, redirect the user
-    pub(crate) fn send(sess: Session, redirect_url: &str, handler: &str, msg: &str, err: Option<String>) -> HttpResponse {
-        let error_message = match err {
-            Some(error) => format!("({}) {}: {}", handler, msg, error),
-            None => format!("({}) {}", handler, msg),
+    // log, purge, redirect the user with the error code and a correlation id
+    // For browser navigation routes (/login, /callback, /logout)
+    pub(crate) fn send(sess: Session, redirect_url: &str, handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+        let error_id = Error::log(handler, code, msg, err);
+        let event = match handler {
+            "login" | "stepup" | "callback" => AuditEvent::LoginFailure,
+            _ => AuditEvent::SessionPurged,
         };
-
-        error!("{}", error_message);
+        audit(event, &sess, Some(code.as_str()));
+        publish(SessionEventKind::LoggedOut, &sess);
         sess.purge();
+
+        return HttpResponse::Found()
+        .insert_header(("Location", Error::location(redirect_url, code, &error_id)))
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .insert_header(("Pragma", "no-cache"))
+        .finish();
+    }
+
+    // log, keep the session, redirect the user with the error code and a correlation id
+    // For navigation requests with bad input, anyone can link to them and must not be able to log the user out
+    pub(crate) fn redirect(redirect_url: &str, handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+        let error_id = Uuid::new_v4().to_string();
+        warn!("{}", Error::message(handler, code, &error_id, msg, err));
+
         return HttpResponse::Found()
-        .insert_header(("Location", redirect_url))
+        .insert_header(("Location", Error::location(redirect_url, code, &error_id)))
         .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
         .insert_header(("Pragma", "no-cache"))
         .finish();
     }
+
+    // log, purge, answer with a status code and a JSON body
+    // For routes the web client calls with fetch (/sessionstatus, /details), a redirect would be followed into HTML
+    pub(crate) fn json(sess: Session, handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+        let error_id = Error::log(handler, code, msg, err);
+        audit(AuditEvent::SessionPurged, &sess, Some(code.as_str()));
+        publish(SessionEventKind::LoggedOut, &sess);
+        sess.purge();
+
+        return HttpResponse::build(code.status())
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .insert_header(("Pragma", "no-cache"))
+        .json(json!({"error": code.as_str(), "error_id": error_id, "retryable": false}));
+    }
+
+    // log, keep the session, answer with a status code and a JSON body
+    // For requests that are refused without anything being wrong with the session, a forged request must not log the user out
+    pub(crate) fn reject(handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+        let error_id = Uuid::new_v4().to_string();
+        warn!("{}", Error::message(handler, code, &error_id, msg, err));
+
+        return HttpResponse::build(code.status())
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .insert_header(("Pragma", "no-cache"))
+        .json(json!({"error": code.as_str(), "error_id": error_id, "retryable": false}));
+    }
+
+    // log, keep the session, answer 503 with Retry-After
+    // For transient failures (provider unreachable, timeouts, 5xx), the same session can succeed on the next call
+    pub(crate) fn transient(handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> HttpResponse {
+        let error_id = Uuid::new_v4().to_string();
+        warn!("{}", Error::message(handler, code, &error_id, msg, err));
+
+        return HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", RETRY_AFTER_SECS.to_string()))
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .insert_header(("Pragma", "no-cache"))
+        .json(json!({"error": code.as_str(), "error_id": error_id, "retryable": true}));
+    }
+
+    // Returns the error_id, the client gets the same id
+    fn log(handler: &str, code: ErrorCode, msg: &str, err: Option<String>) -> String {
+        let error_id = Uuid::new_v4().to_string();
+        error!("{}", Error::message(handler, code, &error_id, msg, err));
+        return error_id;
+    }
+
+    // error_redirect_url is validated at startup, the fallback only keeps the redirect working
+    fn location(redirect_url: &str, code: ErrorCode, error_id: &str) -> String {
+        match Url::parse(redirect_url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair("error", code.as_str()).append_pair("error_id", error_id);
+                return url.to_string();
+            }
+            Err(_) => return redirect_url.to_string(),
+        }
+    }
+
+    fn message(handler: &str, code: ErrorCode, error_id: &str, msg: &str, err: Option<String>) -> String {
+        match err {
+            Some(error) => return format!("[{}] ({}) [{} {}] {}: {}", request_id(), handler, code.as_str(), error_id, msg, error),
+            None => return format!("[{}] ({}) [{} {}] {}", request_id(), handler, code.as_str(), error_id, msg),
+        }
+    }
+}
+
+// Whether a failed token request may succeed if sent again with the same grant
+// Transient: the provider could not be reached, timed out, or answered with a server error
+// Terminal: the provider rejected the grant or the client (invalid_grant, invalid_client, ...)
+pub(crate) fn is_transient_token_error<RE: StdError + 'static>(error: &RequestTokenError<RE, StandardErrorResponse<CoreErrorResponseType>>) -> bool {
+    match error {
+        RequestTokenError::Request(_) => return true, // Connect errors and timeouts, retries are already used up
+        RequestTokenError::ServerResponse(response) => {
+            match response.error() {
+                CoreErrorResponseType::Extension(code) => return code == "server_error" || code == "temporarily_unavailable",
+                _ => return false,
+            }
+        }
+        RequestTokenError::Parse(_, _) => return true, // A non-JSON error body, usually a gateway error page
+        RequestTokenError::Other(_) => return true, // An empty error body or an unexpected content type
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    type TokenError = RequestTokenError<io::Error, StandardErrorResponse<CoreErrorResponseType>>;
+
+    fn server_response(code: CoreErrorResponseType) -> TokenError {
+        return RequestTokenError::ServerResponse(StandardErrorResponse::new(code, None, None));
+    }
+
+    #[test]
+    fn unreachable_provider_and_unparsable_answers_are_transient() {
+        let request: TokenError = RequestTokenError::Request(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert!(is_transient_token_error(&request));
+
+        let body = b"<html>502 Bad Gateway</html>".to_vec();
+        let parse_error = match serde_path_to_error::deserialize::<_, serde_json::Value>(&mut serde_json::Deserializer::from_slice(&body)) {
+            Ok(_) => panic!("an html page is not json"),
+            Err(error) => error,
+        };
+        let parse: TokenError = RequestTokenError::Parse(parse_error, body);
+        assert!(is_transient_token_error(&parse));
+    }
+
+    #[test]
+    fn only_server_side_error_codes_are_transient() {
+        assert!(is_transient_token_error(&server_response(CoreErrorResponseType::Extension("temporarily_unavailable".to_string()))));
+        assert!(is_transient_token_error(&server_response(CoreErrorResponseType::Extension("server_error".to_string()))));
+        assert!(!is_transient_token_error(&server_response(CoreErrorResponseType::InvalidGrant)));
+        assert!(!is_transient_token_error(&server_response(CoreErrorResponseType::InvalidClient)));
+    }
 }
 
 ////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,236 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::audit::session_id_hash;
+use better_logger::logger::{error, warn};
+use std::sync::OnceLock;
+use actix_session::Session;
+use futures_util::StreamExt;
+use redis::{Client, RedisResult};
+use redis::aio::MultiplexedConnection;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::time::{sleep, Duration as tokioDuration};
+
+// Every instance publishes and subscribes here, so an event reaches the /events streams of all instances
+const EVENTS_CHANNEL: &str = "gobsg:session_events";
+
+// Set once at startup when session_events is enabled, unset = events are not published
+static EVENT_SENDER: OnceLock<UnboundedSender<String>> = OnceLock::new();
+
+// expiring_soon is not published, every stream works it out from token_expiry
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum SessionEventKind {
+    #[serde(rename = "logged_in")]
+    LoggedIn,
+    #[serde(rename = "refreshed")]
+    Refreshed,
+    #[serde(rename = "logged_out")]
+    LoggedOut,
+}
+
+impl SessionEventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            SessionEventKind::LoggedIn => return "logged_in",
+            SessionEventKind::Refreshed => return "refreshed",
+            SessionEventKind::LoggedOut => return "logged_out",
+        }
+    }
+}
+
+// The message on EVENTS_CHANNEL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionEvent {
+    pub(crate) event: SessionEventKind,
+    pub(crate) user_id: String,
+    pub(crate) session: Option<String>, // session_id_hash
+    pub(crate) token_expiry: Option<i64>,
+}
+
+impl SessionEvent {
+    // A missing hash on either side matches nothing, an event never reaches every session of the user
+    pub(crate) fn is_for(&self, user_id: &str, session: Option<&str>) -> bool {
+        if self.user_id != user_id {
+            return false;
+        }
+        match (&self.session, session) {
+            (Some(event_session), Some(session)) => return event_session == session,
+            _ => return false,
+        }
+    }
+}
+
+// Hands the events received from Redis to every /events stream of this instance
+pub(crate) struct EventHub {
+    sender: Sender<SessionEvent>,
+}
+
+impl EventHub {
+    pub(crate) fn subscribe(&self) -> Receiver<SessionEvent> {
+        return self.sender.subscribe();
+    }
+}
+
+// One connection publishes, one subscribes, both reconnect on their own
+pub(crate) fn spawn_event_tasks(config_settings: &MainConfiguration) -> Result<EventHub, String> {
+    let client = match Client::open(config_settings.redis_address.as_str()) {
+        Ok(client) => client,
+        Err(error) => return Err(format!("{:?}", error)),
+    };
+
+    let (publish_sender, mut publish_receiver) = unbounded_channel::<String>();
+    if EVENT_SENDER.set(publish_sender).is_err() {
+        return Err("session event tasks already started".to_string());
+    }
+    let publish_client = client.clone();
+    tokio::spawn(async move {
+        let mut connection: Option<MultiplexedConnection> = None;
+        while let Some(payload) = publish_receiver.recv().await {
+            if connection.is_none() {
+                match publish_client.get_multiplexed_async_connection().await {
+                    Ok(new_connection) => connection = Some(new_connection),
+                    Err(error) => {
+                        error!("(events) redis connection failed, event dropped: {:?}", error);
+                        continue;
+                    }
+                }
+            }
+            if let Some(open_connection) = connection.as_mut() {
+                let result: RedisResult<i64> = redis::cmd("PUBLISH").arg(EVENTS_CHANNEL).arg(&payload).query_async(open_connection).await;
+                if let Err(error) = result {
+                    error!("(events) publish failed, event dropped: {:?}", error);
+                    connection = None;
+                }
+            }
+        }
+    });
+
+    let (sender, _) = channel::<SessionEvent>(256);
+    let hub_sender = sender.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(error) = receive_events(&client, &hub_sender).await {
+                warn!("(events) subscription lost, reconnecting in 1s: {}", error);
+            }
+            sleep(tokioDuration::from_secs(1)).await;
+        }
+    });
+
+    return Ok(EventHub { sender: sender });
+}
+
+async fn receive_events(client: &Client, hub_sender: &Sender<SessionEvent>) -> Result<(), String> {
+    let mut pubsub = match client.get_async_pubsub().await {
+        Ok(pubsub) => pubsub,
+        Err(error) => return Err(format!("{:?}", error)),
+    };
+    if let Err(error) = pubsub.subscribe(EVENTS_CHANNEL).await {
+        return Err(format!("{:?}", error));
+    }
+
+    let mut messages = pubsub.into_on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = match message.get_payload() {
+            Ok(payload) => payload,
+            Err(error) => {
+                warn!("(events) unreadable message: {:?}", error);
+                continue;
+            }
+        };
+        match serde_json::from_str::<SessionEvent>(&payload) {
+            Ok(event) => {
+                // Err = no /events stream is open on this instance
+                if hub_sender.send(event).is_err() {
+                    continue;
+                }
+            }
+            Err(error) => warn!("(events) unknown message: {:?}", error),
+        }
+    }
+    return Err("connection closed".to_string());
+}
+
+// Publishes an event for the session, call before the session is purged so the user is still known
+// Nothing is published for a session that never logged in
+pub(crate) fn publish(event: SessionEventKind, session: &Session) {
+    let sender = match EVENT_SENDER.get() {
+        Some(sender) => sender,
+        None => return,
+    };
+    let user_id = match session.get::<String>("user_id") {
+        Ok(Some(user_id)) => user_id,
+        _ => return,
+    };
+    let token_expiry = match session.get::<i64>("token_expiry") {
+        Ok(value) => value,
+        Err(_) => None,
+    };
+
+    let message = SessionEvent {
+        event: event,
+        user_id: user_id,
+        session: session_id_hash(session),
+        token_expiry: token_expiry,
+    };
+    let payload = match serde_json::to_string(&message) {
+        Ok(payload) => payload,
+        Err(error) => {
+            error!("(events) {} not published: {:?}", event.as_str(), error);
+            return;
+        }
+    };
+    if sender.send(payload).is_err() {
+        error!("(events) event task stopped, {} dropped", event.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(session: Option<&str>) -> SessionEvent {
+        return SessionEvent {
+            event: SessionEventKind::LoggedOut,
+            user_id: "user-1".to_string(),
+            session: session.map(|session| session.to_string()),
+            token_expiry: None,
+        };
+    }
+
+    #[test]
+    fn session_event_reaches_only_its_session() {
+        let message = event(Some("hash-a"));
+        assert!(message.is_for("user-1", Some("hash-a")));
+        assert!(!message.is_for("user-1", Some("hash-b")));
+        assert!(!message.is_for("user-1", None));
+        assert!(!message.is_for("user-2", Some("hash-a")));
+    }
+
+    #[test]
+    fn missing_session_hash_is_not_a_broadcast() {
+        let message: SessionEvent = serde_json::from_str(r#"{"event": "logged_out", "user_id": "user-1", "session": null, "token_expiry": null}"#).unwrap();
+        assert!(!message.is_for("user-1", Some("hash-a")));
+        assert!(!message.is_for("user-1", None));
+
+        let message = event(None);
+        assert!(!message.is_for("user-1", Some("hash-a")));
+        assert!(!message.is_for("user-1", None));
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,158 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::clientauth::load_signing_key;
+use std::str::FromStr;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde_json::{Map, Value};
+use chrono::Utc;
+use uuid::Uuid;
+use url::Url;
+
+const REQUEST_OBJECT_TYPE: &str = "oauth-authz-req+jwt"; // RFC 9101 section 10.8
+const REQUEST_OBJECT_LIFETIME_SECS: i64 = 60;
+
+// Signs authorization requests as request objects (JAR, RFC 9101)
+// Built once at startup, None when request_object_key_path is not set
+pub(crate) struct RequestObjectSigner {
+    key: EncodingKey,
+    header: Header,
+    client_id: String,
+    audience: String,
+}
+
+impl RequestObjectSigner {
+    pub(crate) fn new(config_settings: &MainConfiguration) -> Result<Option<RequestObjectSigner>, String> {
+        let key_path = match &config_settings.request_object_key_path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let algorithm = match Algorithm::from_str(config_settings.request_object_alg.as_str()) {
+            Ok(algorithm) => algorithm,
+            Err(error) => return Err(format!("request_object_alg {:?}: {:?}", config_settings.request_object_alg, error)),
+        };
+        let key = load_signing_key(key_path, algorithm)?;
+
+        let mut header = Header::new(algorithm);
+        header.typ = Some(REQUEST_OBJECT_TYPE.to_string());
+        header.kid = config_settings.request_object_key_id.clone();
+
+        return Ok(Some(RequestObjectSigner {
+            key: key,
+            header: header,
+            client_id: config_settings.client.clone(),
+            audience: config_settings.issuer_url.clone(),
+        }));
+    }
+
+    // Moves every parameter of the authorization URL into a signed request object
+    // client_id, response_type and scope stay in the query as well, OpenID Connect requires them there
+    pub(crate) fn sign(&self, auth_url: &Url) -> Result<Url, String> {
+        let mut claims = Map::new();
+        for (name, value) in auth_url.query_pairs() {
+            let claim = claim_value(name.as_ref(), value.into_owned());
+            claims.insert(name.into_owned(), claim);
+        }
+
+        let now = Utc::now().timestamp();
+        claims.insert("iss".to_string(), Value::String(self.client_id.clone()));
+        claims.insert("aud".to_string(), Value::String(self.audience.clone()));
+        claims.insert("jti".to_string(), Value::String(Uuid::new_v4().to_string()));
+        claims.insert("iat".to_string(), Value::from(now));
+        claims.insert("nbf".to_string(), Value::from(now));
+        claims.insert("exp".to_string(), Value::from(now + REQUEST_OBJECT_LIFETIME_SECS));
+
+        let request_object = match encode(&self.header, &claims, &self.key) {
+            Ok(jwt) => jwt,
+            Err(error) => return Err(format!("request object signing failed: {:?}", error)),
+        };
+
+        let kept: Vec<(String, String)> = auth_url.query_pairs()
+            .filter(|(name, _)| matches!(name.as_ref(), "client_id" | "response_type" | "scope"))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+
+        let mut signed_url = auth_url.clone();
+        {
+            let mut query = signed_url.query_pairs_mut();
+            query.clear();
+            for (name, value) in kept.iter() {
+                query.append_pair(name, value);
+            }
+            query.append_pair("request", &request_object);
+        }
+
+        return Ok(signed_url);
+    }
+}
+
+// Query parameters are all strings, but in a request object max_age is a JSON number (OpenID Connect Core 6.1)
+fn claim_value(name: &str, value: String) -> Value {
+    if name == "max_age" {
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Value::from(seconds);
+        }
+    }
+    return Value::String(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    fn test_signer() -> RequestObjectSigner {
+        let mut header = Header::new(Algorithm::HS256);
+        header.typ = Some(REQUEST_OBJECT_TYPE.to_string());
+        return RequestObjectSigner {
+            key: EncodingKey::from_secret(b"test-secret"),
+            header: header,
+            client_id: "gobsg".to_string(),
+            audience: "https://issuer.example".to_string(),
+        };
+    }
+
+    fn request_object_claims(signed_url: &Url) -> Map<String, Value> {
+        let request_object = signed_url.query_pairs().find(|(name, _)| name == "request").map(|(_, value)| value.into_owned()).unwrap();
+        let payload = request_object.split('.').nth(1).unwrap();
+        return serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn max_age_is_a_number() {
+        let auth_url = Url::parse("https://issuer.example/auth?client_id=gobsg&response_type=code&scope=openid&state=abc&max_age=0").unwrap();
+        let claims = request_object_claims(&test_signer().sign(&auth_url).unwrap());
+        assert_eq!(claims.get("max_age"), Some(&Value::from(0u64)));
+        assert_eq!(claims.get("state"), Some(&Value::String("abc".to_string())));
+    }
+
+    #[test]
+    fn only_required_parameters_stay_in_the_query() {
+        let auth_url = Url::parse("https://issuer.example/auth?client_id=gobsg&response_type=code&scope=openid&state=abc&nonce=n").unwrap();
+        let signed_url = test_signer().sign(&auth_url).unwrap();
+        let names: Vec<String> = signed_url.query_pairs().map(|(name, _)| name.into_owned()).collect();
+        assert_eq!(names, vec!["client_id", "response_type", "scope", "request"]);
+
+        let claims = request_object_claims(&signed_url);
+        assert_eq!(claims.get("iss"), Some(&Value::String("gobsg".to_string())));
+        assert_eq!(claims.get("aud"), Some(&Value::String("https://issuer.example".to_string())));
+        assert_eq!(claims.get("nonce"), Some(&Value::String("n".to_string())));
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
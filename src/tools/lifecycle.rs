@@ -0,0 +1,187 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use better_logger::logger::info;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use actix_web::Error as actixError;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+#[cfg(windows)]
+use tokio::signal::windows::{ctrl_c, CtrlC};
+use tokio::time::{sleep, Duration as tokioDuration};
+
+// Shared between main, the signal task and every worker
+#[derive(Default)]
+pub(crate) struct Lifecycle {
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+    in_flight_at_signal: AtomicUsize,
+    aborted: AtomicUsize,
+    signal_name: Mutex<Option<String>>,
+}
+
+// Summary for the final log message
+pub(crate) struct ShutdownReport {
+    pub(crate) signal_name: String,
+    pub(crate) in_flight_at_signal: usize,
+    pub(crate) aborted: usize,
+}
+
+impl Lifecycle {
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        return self.shutting_down.load(Ordering::SeqCst);
+    }
+
+    // Readiness fails from this point on
+    fn begin_shutdown(&self, signal_name: &str) {
+        self.in_flight_at_signal.store(self.in_flight.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.shutting_down.store(true, Ordering::SeqCst);
+        match self.signal_name.lock() {
+            Ok(mut guard) => *guard = Some(signal_name.to_string()),
+            Err(poisoned) => *poisoned.into_inner() = Some(signal_name.to_string()),
+        }
+    }
+
+    // None if the server stopped without a signal
+    pub(crate) fn report(&self) -> Option<ShutdownReport> {
+        let signal_name = match self.signal_name.lock() {
+            Ok(guard) => guard.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+
+        return signal_name.map(|name| ShutdownReport {
+            signal_name: name,
+            in_flight_at_signal: self.in_flight_at_signal.load(Ordering::SeqCst),
+            aborted: self.aborted.load(Ordering::SeqCst),
+        });
+    }
+}
+
+// Counts a request as in flight until it completes
+// If the request future is dropped during shutdown (shutdown_timeout reached), it is counted as aborted
+struct InFlightGuard {
+    lifecycle: Arc<Lifecycle>,
+    completed: bool,
+}
+
+impl InFlightGuard {
+    fn new(lifecycle: Arc<Lifecycle>) -> InFlightGuard {
+        lifecycle.in_flight.fetch_add(1, Ordering::SeqCst);
+        return InFlightGuard { lifecycle: lifecycle, completed: false };
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.lifecycle.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if !self.completed && self.lifecycle.is_shutting_down() {
+            self.lifecycle.aborted.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+pub(crate) async fn track_in_flight(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actixError> {
+    let mut guard = req.app_data::<Data<Lifecycle>>().map(|lifecycle| InFlightGuard::new(lifecycle.clone().into_inner()));
+
+    let response = next.call(req).await;
+    if let Some(guard) = guard.as_mut() {
+        guard.completed = true;
+    }
+    return response;
+}
+
+// SIGTERM and SIGINT, registered before the server runs so a failure stops startup
+#[cfg(unix)]
+struct ShutdownSignals {
+    sigterm: Signal,
+    sigint: Signal,
+}
+
+#[cfg(unix)]
+impl ShutdownSignals {
+    fn new() -> Result<ShutdownSignals, String> {
+        let sigterm = match signal(SignalKind::terminate()) {
+            Ok(stream) => stream,
+            Err(error) => return Err(format!("failed to listen for SIGTERM: {:?}", error)),
+        };
+        let sigint = match signal(SignalKind::interrupt()) {
+            Ok(stream) => stream,
+            Err(error) => return Err(format!("failed to listen for SIGINT: {:?}", error)),
+        };
+        return Ok(ShutdownSignals { sigterm: sigterm, sigint: sigint });
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.sigterm.recv() => return "SIGTERM",
+            _ = self.sigint.recv() => return "SIGINT",
+        }
+    }
+}
+
+// Ctrl-C, the only shutdown signal outside unix
+#[cfg(windows)]
+struct ShutdownSignals {
+    ctrl_c: CtrlC,
+}
+
+#[cfg(windows)]
+impl ShutdownSignals {
+    fn new() -> Result<ShutdownSignals, String> {
+        match ctrl_c() {
+            Ok(stream) => return Ok(ShutdownSignals { ctrl_c: stream }),
+            Err(error) => return Err(format!("failed to listen for Ctrl-C: {:?}", error)),
+        }
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        self.ctrl_c.recv().await;
+        return "CTRL_C";
+    }
+}
+
+// Replaces actix's own signal handling, so readiness can fail before the server stops accepting connections
+// The servers run with disable_signals(), so without the handlers the process could not be stopped: Err is fatal
+pub(crate) fn spawn_signal_task(lifecycle: Arc<Lifecycle>, server_handles: Vec<ServerHandle>, readiness_delay_secs: u64) -> Result<(), String> {
+    let mut signals = ShutdownSignals::new()?;
+
+    tokio::spawn(async move {
+        let signal_name = signals.recv().await;
+
+        lifecycle.begin_shutdown(signal_name);
+        info!("(lifecycle) received {}, readiness is now failing, {} request(s) in flight", signal_name, lifecycle.in_flight.load(Ordering::SeqCst));
+
+        // Give load balancers time to see the failing readiness before new connections are refused
+        if readiness_delay_secs > 0 {
+            sleep(tokioDuration::from_secs(readiness_delay_secs)).await;
+        }
+
+        // Stop accepting connections and drain in-flight requests, bounded by shutdown_timeout_secs
+        for server_handle in server_handles {
+            server_handle.stop(true).await;
+        }
+    });
+    return Ok(());
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
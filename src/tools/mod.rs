@@ -15,5 +15,22 @@
 
 pub(crate) mod error;
 pub(crate) mod settings;
+pub(crate) mod provider;
+pub(crate) mod startup;
+pub(crate) mod lifecycle;
+pub(crate) mod tls;
+pub(crate) mod clientauth;
+pub(crate) mod providerhttp;
+pub(crate) mod par;
+pub(crate) mod jar;
+pub(crate) mod dpop;
+pub(crate) mod responsemode;
+pub(crate) mod claims;
+pub(crate) mod requestid;
+pub(crate) mod audit;
+pub(crate) mod telemetry;
+pub(crate) mod sessioninfo;
+pub(crate) mod events;
+pub(crate) mod tokenexchange;
 
 ////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,79 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::clientauth::ClientAuthentication;
+use crate::tools::providerhttp::ProviderHttp;
+use serde::Deserialize;
+use url::Url;
+
+// RFC 9126 section 2.2
+#[derive(Deserialize)]
+struct PushedAuthorizationResponse {
+    request_uri: String,
+}
+
+// Pushed Authorization Request (RFC 9126)
+// POSTs the parameters of the full authorization URL to the PAR endpoint, returns the authorization URL to redirect to
+// The redirect then only carries client_id and request_uri
+pub(crate) async fn push_authorization_request(
+    provider_http: &ProviderHttp,
+    client_auth: &ClientAuthentication,
+    par_endpoint: &str,
+    issuer: &str,
+    auth_url: &Url,
+) -> Result<Url, String> {
+    let mut form: Vec<(String, String)> = auth_url.query_pairs().into_owned().filter(|(name, _)| name != "client_id").collect();
+
+    // The PAR endpoint authenticates the client the same way as the token endpoint, the issuer is the assertion audience
+    let credentials = client_auth.form_credentials(issuer)?;
+    for (name, value) in credentials.params {
+        form.push((name.to_string(), value));
+    }
+
+    let mut request = provider_http.post(par_endpoint).form(&form);
+    if let Some((client_id, client_secret)) = credentials.basic {
+        request = request.basic_auth(client_id, Some(client_secret));
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(error) => return Err(format!("PAR request failed: {:?}", error)),
+    };
+    let status = response.status();
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(error) => return Err(format!("PAR response failed: {:?}", error)),
+    };
+    if !status.is_success() {
+        return Err(format!("PAR endpoint returned {}: {}", status, body));
+    }
+
+    let pushed = match serde_json::from_str::<PushedAuthorizationResponse>(&body) {
+        Ok(pushed) => pushed,
+        Err(error) => return Err(format!("PAR response is not valid: {:?}", error)),
+    };
+
+    // Same authorization endpoint, only the client_id is kept from the original query
+    let client_id = match auth_url.query_pairs().find(|(name, _)| name == "client_id") {
+        Some((_, value)) => value.into_owned(),
+        None => return Err("authorization URL has no client_id".to_string()),
+    };
+    let mut redirect_url = auth_url.clone();
+    redirect_url.query_pairs_mut().clear().append_pair("client_id", &client_id).append_pair("request_uri", &pushed.request_uri);
+
+    return Ok(redirect_url);
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,302 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::OpenidClient;
+use crate::tools::settings::MainConfiguration;
+use crate::tools::startup::with_backoff;
+use crate::tools::clientauth::ClientAuthentication;
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::jar::RequestObjectSigner;
+use better_logger::logger::{debugx, error};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use std::str::FromStr;
+use actix_web::web::Data;
+use openidconnect::{IssuerUrl, ClientId, RedirectUrl, TokenUrl, UserInfoUrl, AdditionalProviderMetadata, ProviderMetadata};
+use openidconnect::core::{
+    CoreClient, CoreAuthDisplay, CoreClientAuthMethod, CoreClaimName, CoreClaimType, CoreGrantType, CoreJweContentEncryptionAlgorithm, 
+    CoreJweKeyManagementAlgorithm, CoreJsonWebKey, CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::jwk::Jwk;
+use tokio::sync::Mutex as tokioMutex;
+use tokio::time::{interval, MissedTickBehavior};
+use std::time::Duration as timeDuration;
+use tokio::time::Duration as tokioDuration;
+
+// Discovery fields that openidconnect does not know about
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct ProviderExtras {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mtls_endpoint_aliases: Option<MtlsEndpointAliases>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pushed_authorization_request_endpoint: Option<String>, // RFC 9126
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) authorization_response_iss_parameter_supported: Option<bool>, // RFC 9207
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) authorization_signing_alg_values_supported: Option<Vec<String>>, // JARM
+    #[serde(skip)]
+    pub(crate) signing_keys: Vec<Jwk>, // The provider JWKS for JARM, filled in after discovery
+    #[serde(skip)]
+    pub(crate) jarm_algorithms: Vec<Algorithm>, // Accepted JARM signature algorithms, filled in after discovery
+}
+impl AdditionalProviderMetadata for ProviderExtras {}
+
+// RFC 8705 section 5
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct MtlsEndpointAliases {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) token_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) userinfo_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) pushed_authorization_request_endpoint: Option<String>,
+}
+
+pub(crate) type ProviderMetadataWithExtras = ProviderMetadata<
+    ProviderExtras,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+// The result of one discovery, the client does not keep the extra discovery fields
+pub(crate) struct ProviderState {
+    pub(crate) client: OpenidClient,
+    pub(crate) extras: ProviderExtras, // Mutual TLS aliases already applied
+}
+
+// Holds the current provider state, the state is swapped when the provider metadata and JWKS are refreshed
+// The state is None until the first discovery succeeds (lazy_discovery)
+pub(crate) struct ProviderClient {
+    state: RwLock<Option<Arc<ProviderState>>>, // Client and extras in one lock, a request never sees them from different discoveries
+    client_auth: ClientAuthentication,
+    request_signer: Option<RequestObjectSigner>, // None = no request objects
+    http: Data<ProviderHttp>,
+    last_unknown_key_refresh: tokioMutex<Option<Instant>>, // Also serializes refreshes, so only one discovery runs at a time
+}
+
+impl ProviderClient {
+    // Starts without a client, call get() (or spawn_discovery_task with lazy_discovery) to run discovery
+    pub(crate) fn new(client_auth: ClientAuthentication, request_signer: Option<RequestObjectSigner>, http: Data<ProviderHttp>) -> ProviderClient {
+        return ProviderClient {
+            state: RwLock::new(None),
+            client_auth: client_auth,
+            request_signer: request_signer,
+            http: http,
+            last_unknown_key_refresh: tokioMutex::new(None), // The first unknown key after boot is always looked up
+        };
+    }
+
+    // Handlers take a snapshot of the state, a refresh never changes a state that is already in use
+    pub(crate) fn current(&self) -> Option<Arc<ProviderState>> {
+        match self.state.read() {
+            Ok(guard) => return guard.clone(),
+            Err(poisoned) => return poisoned.into_inner().clone(),
+        }
+    }
+
+    pub(crate) fn client_auth(&self) -> &ClientAuthentication {
+        return &self.client_auth;
+    }
+
+    pub(crate) fn request_signer(&self) -> Option<&RequestObjectSigner> {
+        return self.request_signer.as_ref();
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        return self.current().is_some();
+    }
+
+    // Returns the current state, runs discovery first if it has not succeeded yet
+    pub(crate) async fn get(&self, config_settings: &MainConfiguration) -> Result<Arc<ProviderState>, String> {
+        if let Some(state) = self.current() {
+            return Ok(state);
+        }
+
+        let _serialized = self.last_unknown_key_refresh.lock().await;
+        if let Some(state) = self.current() { // Another request finished discovery while this one was waiting
+            return Ok(state);
+        }
+        return self.swap(config_settings).await;
+    }
+
+    // Run discovery again (this also fetches the JWKS) and swap the state
+    pub(crate) async fn refresh(&self, config_settings: &MainConfiguration) -> Result<(), String> {
+        let _serialized = self.last_unknown_key_refresh.lock().await;
+        self.swap(config_settings).await?;
+        return Ok(());
+    }
+
+    // Called when an id token is signed with a key that is not in the current JWKS
+    // Skipped if the previous one was recent, so a bad token cannot be used to flood the provider
+    // Discovery and the background refresh do not count, a key rotated right after a deploy is found at once
+    pub(crate) async fn refresh_for_unknown_key(&self, config_settings: &MainConfiguration) -> Result<(), String> {
+        let mut last_unknown_key_refresh = self.last_unknown_key_refresh.lock().await;
+        if let Some(last) = *last_unknown_key_refresh {
+            if last.elapsed() < timeDuration::from_secs(config_settings.provider_refresh_min_interval_secs) {
+                return Ok(());
+            }
+        }
+        *last_unknown_key_refresh = Some(Instant::now());
+        self.swap(config_settings).await?;
+        return Ok(());
+    }
+
+    async fn swap(&self, config_settings: &MainConfiguration) -> Result<Arc<ProviderState>, String> {
+        let new_state = Arc::new(self.discover(config_settings).await?);
+        match self.state.write() {
+            Ok(mut guard) => *guard = Some(new_state.clone()),
+            Err(poisoned) => *poisoned.into_inner() = Some(new_state.clone()),
+        }
+        debugx!("provider metadata and JWKS refreshed");
+        return Ok(new_state);
+    }
+
+    // Fetch the provider metadata and JWKS, then build the client
+    async fn discover(&self, config_settings: &MainConfiguration) -> Result<ProviderState, String> {
+        let issuer_url = match IssuerUrl::new(config_settings.issuer_url.as_str().to_string()) {
+            Ok(url) => url,
+            Err(error) => return Err(format!("{:?}", error)),
+        };
+
+        let mut provider_metadata = match ProviderMetadataWithExtras::discover_async(issuer_url, self.http.get_ref()).await {
+            Ok(data) => data,
+            Err(error) => return Err(format!("{:?}", error)),
+        };
+
+        // With a client certificate, use the mutual TLS endpoints the provider advertises (RFC 8705)
+        // Tokens are then bound to the certificate, and the refresh is sent with the same certificate
+        if self.http.has_client_certificate() {
+            if let Some(aliases) = provider_metadata.additional_metadata().mtls_endpoint_aliases.clone() {
+                if let Some(token_endpoint) = aliases.token_endpoint {
+                    match TokenUrl::new(token_endpoint) {
+                        Ok(url) => provider_metadata = provider_metadata.set_token_endpoint(Some(url)),
+                        Err(error) => return Err(format!("mtls token_endpoint: {:?}", error)),
+                    }
+                }
+                if let Some(userinfo_endpoint) = aliases.userinfo_endpoint {
+                    match UserInfoUrl::new(userinfo_endpoint) {
+                        Ok(url) => provider_metadata = provider_metadata.set_userinfo_endpoint(Some(url)),
+                        Err(error) => return Err(format!("mtls userinfo_endpoint: {:?}", error)),
+                    }
+                }
+            }
+        }
+        let mut extras = provider_metadata.additional_metadata().clone();
+        extras.signing_keys = signing_keys(&provider_metadata);
+        extras.jarm_algorithms = jarm_algorithms(extras.authorization_signing_alg_values_supported.as_deref());
+        if self.http.has_client_certificate() {
+            if let Some(par_endpoint) = extras.mtls_endpoint_aliases.as_ref().and_then(|aliases| aliases.pushed_authorization_request_endpoint.clone()) {
+                extras.pushed_authorization_request_endpoint = Some(par_endpoint);
+            }
+        }
+
+        let redirect_url = match RedirectUrl::new(format!("{}/callback", config_settings.this_server_url)) {
+            Ok(url) => url,
+            Err(error) => return Err(format!("{:?}", error)),
+        };
+
+        let openid_client = {
+            CoreClient::from_provider_metadata(
+                provider_metadata,
+                ClientId::new(config_settings.client.as_str().to_string()),
+                self.client_auth.client_secret() // None for the JWT and TLS methods
+            ).set_redirect_uri(redirect_url).set_auth_type(self.client_auth.auth_type())
+        };
+
+        return Ok(ProviderState {
+            client: openid_client,
+            extras: extras,
+        });
+    }
+}
+
+// The same JWKS as the id token verifier uses, in the form jsonwebtoken verifies with
+// Keys jsonwebtoken does not support are skipped
+fn signing_keys(provider_metadata: &ProviderMetadataWithExtras) -> Vec<Jwk> {
+    let keys = match serde_json::to_value(provider_metadata.jwks()) {
+        Ok(Value::Object(mut jwks)) => {
+            match jwks.remove("keys") {
+                Some(Value::Array(keys)) => keys,
+                _ => Vec::new(),
+            }
+        }
+        _ => Vec::new(),
+    };
+    return keys.into_iter().filter_map(|key| serde_json::from_value::<Jwk>(key).ok()).collect();
+}
+
+// What the provider advertises, RS256 when it advertises nothing (JARM section 4)
+// Only asymmetric algorithms, the JWKS holds public keys and an HMAC "secret" taken from it would be public
+fn jarm_algorithms(advertised: Option<&[String]>) -> Vec<Algorithm> {
+    let advertised = match advertised {
+        Some(names) => names,
+        None => return vec![Algorithm::RS256],
+    };
+    return advertised.iter()
+        .filter_map(|name| Algorithm::from_str(name).ok())
+        .filter(|algorithm| !matches!(algorithm, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512))
+        .collect();
+}
+
+// lazy_discovery: keep trying discovery in the background while the server is already running
+// A login that arrives first runs discovery itself, whichever succeeds first wins
+pub(crate) fn spawn_discovery_task(provider_client: Data<ProviderClient>, config_settings: Data<MainConfiguration>) {
+    tokio::spawn(async move {
+        let result = with_backoff(&config_settings, "provider discovery", || async {
+            if provider_client.is_ready() {
+                return Ok(());
+            }
+            return provider_client.refresh(&config_settings).await;
+        }).await;
+
+        if let Err(error) = result {
+            error!("(provider) lazy discovery gave up, discovery will be attempted on the next login: {}", error);
+        }
+    });
+}
+
+// Periodically refresh the provider metadata and JWKS, runs until the process exits
+pub(crate) fn spawn_refresh_task(provider_client: Data<ProviderClient>, config_settings: Data<MainConfiguration>) {
+    if config_settings.provider_refresh_interval_mins == 0 {
+        return; // Periodic refresh is disabled, unknown keys still trigger a refresh
+    }
+
+    tokio::spawn(async move {
+        let mut time = interval(tokioDuration::from_secs(config_settings.provider_refresh_interval_mins * 60));
+        time.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        time.tick().await;
+        loop {
+            time.tick().await;
+            if let Err(error) = provider_client.refresh(&config_settings).await {
+                // Keep using the current client, the next tick will try again
+                error!("(provider) periodic refresh failed: {}", error);
+            }
+        }
+    });
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,212 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::{current_request_id, request_id, REQUEST_ID_HEADER};
+use crate::tools::telemetry::{inject_trace_context, traced};
+use better_logger::logger::warn;
+use std::fs::read;
+use std::future::Future;
+use std::pin::Pin;
+use openidconnect::{AsyncHttpClient, HttpClientError, HttpRequest, HttpResponse};
+use openidconnect::http::{HeaderMap, HeaderValue, Method, Request, Response};
+use openidconnect::reqwest::{Certificate, Client, ClientBuilder, Identity, NoProxy, Proxy, RequestBuilder};
+use openidconnect::reqwest::redirect::Policy;
+use opentelemetry::trace::SpanKind;
+use tokio::time::sleep;
+use std::time::Duration as timeDuration;
+
+type ProviderHttpError = HttpClientError<openidconnect::reqwest::Error>;
+
+// One shared client for every call to the provider (discovery, JWKS, token requests)
+// Registered as app data, so connections are pooled across requests
+pub(crate) struct ProviderHttp {
+    client: Client,
+    has_client_certificate: bool,
+    retry_max_attempts: u32,
+    retry_initial_delay_ms: u64,
+}
+
+impl ProviderHttp {
+    pub(crate) fn new(config_settings: &MainConfiguration) -> Result<ProviderHttp, String> {
+        let mut builder = ClientBuilder::new()
+            .redirect(Policy::none())
+            .connect_timeout(timeDuration::from_secs(config_settings.provider_connect_timeout_secs))
+            .timeout(timeDuration::from_secs(config_settings.provider_request_timeout_secs))
+            .pool_max_idle_per_host(config_settings.provider_pool_max_idle_per_host)
+            .pool_idle_timeout(timeDuration::from_secs(config_settings.provider_pool_idle_timeout_secs));
+
+        // Client certificate for mutual TLS (RFC 8705)
+        let identity = match (&config_settings.provider_client_cert_path, &config_settings.provider_client_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                let mut pem = read_file(cert_path)?;
+                pem.push(b'\n');
+                pem.extend(read_file(key_path)?);
+                match Identity::from_pem(&pem) {
+                    Ok(identity) => Some(identity),
+                    Err(error) => return Err(format!("provider client certificate failed: {:?}", error)),
+                }
+            }
+            (None, None) => None,
+            _ => return Err("provider_client_cert_path and provider_client_key_path must be set together".to_string()),
+        };
+        let has_client_certificate = identity.is_some();
+        if let Some(identity) = identity {
+            builder = builder.identity(identity);
+        }
+
+        // Extra trusted CAs, for providers behind an internal CA
+        if let Some(bundle_path) = &config_settings.provider_ca_bundle_path {
+            match Certificate::from_pem_bundle(&read_file(bundle_path)?) {
+                Ok(certificates) => {
+                    for certificate in certificates {
+                        builder = builder.add_root_certificate(certificate);
+                    }
+                }
+                Err(error) => return Err(format!("provider_ca_bundle_path {:?}: {:?}", bundle_path, error)),
+            }
+        }
+
+        // Egress proxy for HTTP and HTTPS, credentials can be given in the URL
+        if let Some(proxy_url) = &config_settings.provider_proxy_url {
+            let proxy = match Proxy::all(proxy_url.as_str()) {
+                Ok(proxy) => proxy,
+                Err(error) => return Err(format!("provider_proxy_url: {:?}", error)),
+            };
+            builder = builder.proxy(proxy.no_proxy(config_settings.provider_no_proxy.as_deref().and_then(NoProxy::from_string)));
+        }
+
+        let client = match builder.build() {
+            Ok(client) => client,
+            Err(error) => return Err(format!("{:?}", error)),
+        };
+
+        return Ok(ProviderHttp {
+            client: client,
+            has_client_certificate: has_client_certificate,
+            retry_max_attempts: config_settings.provider_retry_max_attempts.max(1),
+            retry_initial_delay_ms: config_settings.provider_retry_initial_delay_ms,
+        });
+    }
+
+    pub(crate) fn has_client_certificate(&self) -> bool {
+        return self.has_client_certificate;
+    }
+
+    // For provider requests GOBSG builds itself, these are not retried
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        let mut headers = HeaderMap::new();
+        inject_trace_context(&mut headers);
+        let builder = self.client.post(url).headers(headers);
+        match current_request_id() {
+            Some(id) => return builder.header(REQUEST_ID_HEADER, id),
+            None => return builder,
+        }
+    }
+
+    // Every attempt is its own client span, the provider receives it as the parent (traceparent)
+    async fn send_once(&self, request: HttpRequest) -> Result<HttpResponse, ProviderHttpError> {
+        return traced("provider request", SpanKind::Client, self.send_in_span(request)).await;
+    }
+
+    async fn send_in_span(&self, mut request: HttpRequest) -> Result<HttpResponse, ProviderHttpError> {
+        // The provider can correlate its logs with the browser request
+        if let Some(id) = current_request_id() {
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                request.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+        }
+        inject_trace_context(request.headers_mut());
+
+        let reqwest_request = match request.try_into() {
+            Ok(converted) => converted,
+            Err(error) => return Err(HttpClientError::Reqwest(Box::new(error))),
+        };
+        let response = match self.client.execute(reqwest_request).await {
+            Ok(response) => response,
+            Err(error) => return Err(HttpClientError::Reqwest(Box::new(error))),
+        };
+
+        let mut builder = Response::builder().status(response.status()).version(response.version());
+        for (name, value) in response.headers().iter() {
+            builder = builder.header(name, value);
+        }
+
+        let body = match response.bytes().await {
+            Ok(bytes) => bytes.to_vec(),
+            Err(error) => return Err(HttpClientError::Reqwest(Box::new(error))),
+        };
+        match builder.body(body) {
+            Ok(response) => return Ok(response),
+            Err(error) => return Err(HttpClientError::Http(error)),
+        }
+    }
+}
+
+// openidconnect sends every provider request through here
+// Only idempotent requests (discovery, JWKS) are retried, a code exchange or refresh is never sent twice
+impl<'c> AsyncHttpClient<'c> for ProviderHttp {
+    type Error = ProviderHttpError;
+    type Future = Pin<Box<dyn Future<Output = Result<HttpResponse, Self::Error>> + Send + 'c>>;
+
+    fn call(&'c self, request: HttpRequest) -> Self::Future {
+        Box::pin(async move {
+            let idempotent = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+            let max_attempts = if idempotent { self.retry_max_attempts } else { 1 };
+            let mut delay = self.retry_initial_delay_ms;
+            let mut attempt: u32 = 1;
+
+            loop {
+                let next_request = match copy_request(&request) {
+                    Ok(copy) => copy,
+                    Err(error) => return Err(HttpClientError::Http(error)),
+                };
+
+                let result = self.send_once(next_request).await;
+                let retryable = match &result {
+                    Ok(response) => response.status().is_server_error(),
+                    Err(HttpClientError::Reqwest(error)) => error.is_connect() || error.is_timeout(),
+                    Err(_) => false,
+                };
+                if !retryable || attempt >= max_attempts {
+                    return result;
+                }
+
+                warn!("[{}] (provider_http) {} {} failed, attempt {} of {}, retrying in {}ms", request_id(), request.method(), request.uri(), attempt, max_attempts, delay);
+                sleep(timeDuration::from_millis(delay)).await;
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+        })
+    }
+}
+
+// http::Request is not Clone, a retry needs a fresh copy
+pub(crate) fn copy_request(request: &HttpRequest) -> Result<HttpRequest, openidconnect::http::Error> {
+    let mut builder = Request::builder().method(request.method().clone()).uri(request.uri().clone()).version(request.version());
+    for (name, value) in request.headers().iter() {
+        builder = builder.header(name, value);
+    }
+    return builder.body(request.body().clone());
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    match read(path) {
+        Ok(bytes) => return Ok(bytes),
+        Err(error) => return Err(format!("read({:?}) failed: {:?}", path, error)),
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,112 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use actix_web::Error as actixError;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use uuid::Uuid;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Who sent the request being handled, for logs and audit events
+#[derive(Clone)]
+pub(crate) struct RequestContext {
+    pub(crate) request_id: String,
+    pub(crate) ip: Option<String>, // The peer address, a reverse proxy when GOBSG runs behind one
+    pub(crate) forwarded_for: Option<String>, // X-Forwarded-For as received, not trusted
+    pub(crate) user_agent: Option<String>,
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+// None outside of a request (discovery, refresh task)
+pub(crate) fn current_request_context() -> Option<RequestContext> {
+    match REQUEST_CONTEXT.try_with(|context| context.clone()) {
+        Ok(context) => return Some(context),
+        Err(_) => return None,
+    }
+}
+
+pub(crate) fn current_request_id() -> Option<String> {
+    match REQUEST_CONTEXT.try_with(|context| context.request_id.clone()) {
+        Ok(id) => return Some(id),
+        Err(_) => return None,
+    }
+}
+
+// Prefix for log lines, "-" outside of a request
+pub(crate) fn request_id() -> String {
+    match current_request_id() {
+        Some(id) => return id,
+        None => return "-".to_string(),
+    }
+}
+
+// Accepts the X-Request-Id of the caller (a load balancer or the web client), otherwise assigns a new one
+// The id and the caller (IP, user agent) are visible to everything awaited inside the request, the id is returned in the response
+pub(crate) async fn assign_request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actixError> {
+    let incoming = match req.headers().get(REQUEST_ID_HEADER) {
+        Some(value) => {
+            match value.to_str() {
+                Ok(id) if is_valid_request_id(id) => Some(id.to_string()),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+    let request_id = match incoming {
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    let context = RequestContext {
+        request_id: request_id.clone(),
+        ip: req.peer_addr().map(|address| address.ip().to_string()),
+        forwarded_for: header_value(&req, "x-forwarded-for"),
+        user_agent: header_value(&req, "user-agent"),
+    };
+
+    let mut response = REQUEST_CONTEXT.scope(context, next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    return Ok(response);
+}
+
+fn header_value(req: &ServiceRequest, name: &str) -> Option<String> {
+    match req.headers().get(name) {
+        Some(value) => {
+            match value.to_str() {
+                Ok(text) => return Some(text.to_string()),
+                Err(_) => return None,
+            }
+        }
+        None => return None,
+    }
+}
+
+// The id ends up in log lines and headers, only short ids without spaces or control characters are accepted
+fn is_valid_request_id(id: &str) -> bool {
+    return !id.is_empty() && id.len() <= 128 && id.bytes().all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b':'));
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
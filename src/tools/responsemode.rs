@@ -0,0 +1,250 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use std::collections::HashMap;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, PublicKeyUse};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// How the provider returns the authorization response to /callback
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub(crate) enum ResponseMode {
+    #[serde(rename = "query")]
+    Query,
+    #[serde(rename = "form_post")]
+    FormPost,
+    #[serde(rename = "jwt")]
+    Jwt, // JARM, the provider picks the default transport (query for the code flow)
+    #[serde(rename = "query.jwt")]
+    QueryJwt,
+    #[serde(rename = "form_post.jwt")]
+    FormPostJwt,
+}
+
+impl ResponseMode {
+    // Value of the response_mode parameter, None for query (the default of the code flow)
+    pub(crate) fn parameter(&self) -> Option<&'static str> {
+        match self {
+            ResponseMode::Query => return None,
+            ResponseMode::FormPost => return Some("form_post"),
+            ResponseMode::Jwt => return Some("jwt"),
+            ResponseMode::QueryJwt => return Some("query.jwt"),
+            ResponseMode::FormPostJwt => return Some("form_post.jwt"),
+        }
+    }
+
+    // A response that arrives the wrong way is rejected, so the mode cannot be downgraded
+    pub(crate) fn allows_get(&self) -> bool {
+        return !matches!(self, ResponseMode::FormPost | ResponseMode::FormPostJwt);
+    }
+
+    pub(crate) fn allows_post(&self) -> bool {
+        return matches!(self, ResponseMode::FormPost | ResponseMode::FormPostJwt | ResponseMode::Jwt);
+    }
+
+    pub(crate) fn is_jwt(&self) -> bool {
+        return matches!(self, ResponseMode::Jwt | ResponseMode::QueryJwt | ResponseMode::FormPostJwt);
+    }
+}
+
+pub(crate) enum JarmError {
+    NoMatchingKey, // The provider may have rotated its keys, refresh the JWKS and try again
+    Invalid(String),
+}
+
+// JARM: verify the signed response against the provider JWKS, then return its claims as response parameters
+// The alg must be one the provider advertises (allowed_algorithms), never what the header alone says
+// The issuer and audience are checked here, expiry is required
+pub(crate) fn decode_jarm_response(response: &str, signing_keys: &[Jwk], allowed_algorithms: &[Algorithm], issuer: &str, client_id: &str) -> Result<HashMap<String, String>, JarmError> {
+    let header = match decode_header(response) {
+        Ok(header) => header,
+        Err(error) => return Err(JarmError::Invalid(format!("response header: {:?}", error))),
+    };
+    if !allowed_algorithms.contains(&header.alg) {
+        return Err(JarmError::Invalid(format!("alg {:?} is not allowed", header.alg)));
+    }
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+    // Without a kid every signing key of the right type is tried, a wrong key only fails the signature check
+    let candidates = signing_keys.iter().filter(|jwk| key_matches(jwk, header.kid.as_deref(), header.alg));
+    let mut claims = None;
+    for jwk in candidates {
+        let decoding_key = match DecodingKey::from_jwk(jwk) {
+            Ok(key) => key,
+            Err(_) => continue,
+        };
+        match decode::<Map<String, Value>>(response, &decoding_key, &validation) {
+            Ok(data) => {
+                claims = Some(data.claims);
+                break;
+            }
+            Err(error) if *error.kind() == ErrorKind::InvalidSignature => continue,
+            Err(error) => return Err(JarmError::Invalid(format!("response verification: {:?}", error))),
+        }
+    }
+    let claims = match claims {
+        Some(claims) => claims,
+        None => return Err(JarmError::NoMatchingKey),
+    };
+
+    let mut params = HashMap::new();
+    for (name, value) in claims {
+        match value {
+            Value::String(text) => params.insert(name, text),
+            other => params.insert(name, other.to_string()),
+        };
+    }
+    return Ok(params);
+}
+
+// A key can verify the response if its kid, use, alg and key type agree with the header
+fn key_matches(jwk: &Jwk, kid: Option<&str>, algorithm: Algorithm) -> bool {
+    if let Some(kid) = kid {
+        if jwk.common.key_id.as_deref() != Some(kid) {
+            return false;
+        }
+    }
+    if let Some(key_use) = &jwk.common.public_key_use {
+        if *key_use != PublicKeyUse::Signature {
+            return false;
+        }
+    }
+    if let Some(key_algorithm) = &jwk.common.key_algorithm {
+        if key_algorithm.to_string() != format!("{:?}", algorithm) {
+            return false;
+        }
+    }
+    match (&jwk.algorithm, algorithm) {
+        (AlgorithmParameters::RSA(_), Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512) => return true,
+        (AlgorithmParameters::EllipticCurve(_), Algorithm::ES256 | Algorithm::ES384) => return true,
+        (AlgorithmParameters::OctetKeyPair(_), Algorithm::EdDSA) => return true,
+        _ => return false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use jsonwebtoken::jwk::{CommonParameters, EllipticCurve, EllipticCurveKeyParameters, EllipticCurveKeyType, KeyAlgorithm};
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+    use chrono::Utc;
+
+    const ISSUER: &str = "https://issuer.example";
+    const CLIENT_ID: &str = "gobsg";
+
+    // A P-256 key pair, as PKCS#8 for signing and as a JWK for the JWKS
+    fn es256_key(kid: Option<&str>) -> (Vec<u8>, Jwk) {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new()).unwrap().as_ref().to_vec();
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &SystemRandom::new()).unwrap();
+        let public_key = key_pair.public_key().as_ref();
+        let jwk = Jwk {
+            common: CommonParameters { key_id: kid.map(|kid| kid.to_string()), ..Default::default() },
+            algorithm: AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: EllipticCurveKeyType::EC,
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(&public_key[1..33]),
+                y: URL_SAFE_NO_PAD.encode(&public_key[33..65]),
+            }),
+        };
+        return (pkcs8, jwk);
+    }
+
+    fn signed_response(pkcs8: &[u8], kid: Option<&str>, audience: &str) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = kid.map(|kid| kid.to_string());
+        let claims = json!({"iss": ISSUER, "aud": audience, "exp": Utc::now().timestamp() + 60, "code": "c-1", "state": "s-1"});
+        return encode(&header, &claims, &EncodingKey::from_ec_der(pkcs8)).unwrap();
+    }
+
+    #[test]
+    fn valid_response_returns_its_parameters() {
+        let (pkcs8, jwk) = es256_key(Some("k1"));
+        let response = signed_response(&pkcs8, Some("k1"), CLIENT_ID);
+        let params = match decode_jarm_response(&response, &[jwk], &[Algorithm::ES256], ISSUER, CLIENT_ID) {
+            Ok(params) => params,
+            Err(_) => panic!("valid response rejected"),
+        };
+        assert_eq!(params.get("code").map(String::as_str), Some("c-1"));
+        assert_eq!(params.get("state").map(String::as_str), Some("s-1"));
+    }
+
+    #[test]
+    fn alg_not_advertised_is_rejected() {
+        let (pkcs8, jwk) = es256_key(Some("k1"));
+        let response = signed_response(&pkcs8, Some("k1"), CLIENT_ID);
+        assert!(matches!(decode_jarm_response(&response, &[jwk], &[Algorithm::RS256], ISSUER, CLIENT_ID), Err(JarmError::Invalid(_))));
+    }
+
+    #[test]
+    fn hmac_signed_with_a_public_key_is_rejected() {
+        let (_, jwk) = es256_key(Some("k1"));
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".to_string());
+        let claims = json!({"iss": ISSUER, "aud": CLIENT_ID, "exp": Utc::now().timestamp() + 60});
+        let forged = encode(&header, &claims, &EncodingKey::from_secret(b"public material")).unwrap();
+        assert!(matches!(decode_jarm_response(&forged, &[jwk], &[Algorithm::ES256], ISSUER, CLIENT_ID), Err(JarmError::Invalid(_))));
+    }
+
+    #[test]
+    fn without_kid_only_matching_keys_are_tried() {
+        let (_, mut encryption_jwk) = es256_key(None);
+        encryption_jwk.common.public_key_use = Some(PublicKeyUse::Encryption);
+        let (_, mut other_alg_jwk) = es256_key(None);
+        other_alg_jwk.common.key_algorithm = Some(KeyAlgorithm::ES384);
+        let (_, other_jwk) = es256_key(None);
+        let (pkcs8, jwk) = es256_key(None);
+        let response = signed_response(&pkcs8, None, CLIENT_ID);
+
+        assert!(key_matches(&jwk, None, Algorithm::ES256));
+        assert!(!key_matches(&encryption_jwk, None, Algorithm::ES256));
+        assert!(!key_matches(&other_alg_jwk, None, Algorithm::ES256));
+        assert!(!key_matches(&jwk, None, Algorithm::RS256));
+        assert!(!key_matches(&jwk, Some("k1"), Algorithm::ES256));
+
+        let keys = vec![encryption_jwk, other_alg_jwk, other_jwk, jwk];
+        assert!(decode_jarm_response(&response, &keys, &[Algorithm::ES256], ISSUER, CLIENT_ID).is_ok());
+    }
+
+    #[test]
+    fn unknown_key_asks_for_a_refresh() {
+        let (pkcs8, _) = es256_key(Some("new"));
+        let (_, old_jwk) = es256_key(Some("old"));
+        let response = signed_response(&pkcs8, Some("new"), CLIENT_ID);
+        assert!(matches!(decode_jarm_response(&response, std::slice::from_ref(&old_jwk), &[Algorithm::ES256], ISSUER, CLIENT_ID), Err(JarmError::NoMatchingKey)));
+
+        let unsigned_by_any = signed_response(&pkcs8, None, CLIENT_ID);
+        assert!(matches!(decode_jarm_response(&unsigned_by_any, &[old_jwk], &[Algorithm::ES256], ISSUER, CLIENT_ID), Err(JarmError::NoMatchingKey)));
+    }
+
+    #[test]
+    fn wrong_audience_is_rejected() {
+        let (pkcs8, jwk) = es256_key(Some("k1"));
+        let response = signed_response(&pkcs8, Some("k1"), "another-client");
+        assert!(matches!(decode_jarm_response(&response, &[jwk], &[Algorithm::ES256], ISSUER, CLIENT_ID), Err(JarmError::Invalid(_))));
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,97 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use better_logger::logger::error;
+use actix_session::{Session, SessionExt, SessionStatus};
+use actix_web::Error as actixError;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use chrono::{Duration as chronoDuration, Utc};
+use serde::Deserialize;
+use crate::tools::requestid::request_id;
+
+// Redis TTL of a session, extended by the session store every time the session changes
+pub(crate) const SESSION_TTL_DAYS: i64 = 7;
+
+// Optional fields of the /sessionstatus response, see sessionstatus_fields
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub(crate) enum StatusField {
+    #[serde(rename = "token_expiry")]
+    TokenExpiry, // Unix time the access token expires
+    #[serde(rename = "session_expiry")]
+    SessionExpiry, // Unix time the session ends if it does not change before
+    #[serde(rename = "refresh_in")]
+    RefreshIn, // Seconds until the next /sessionstatus call refreshes the tokens
+    #[serde(rename = "refreshed")]
+    Refreshed, // This call refreshed the tokens
+    #[serde(rename = "user_id")]
+    UserId,
+    #[serde(rename = "scopes")]
+    Scopes, // As granted by the provider, null if the token response did not list them
+}
+
+impl StatusField {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            StatusField::TokenExpiry => return "token_expiry",
+            StatusField::SessionExpiry => return "session_expiry",
+            StatusField::RefreshIn => return "refresh_in",
+            StatusField::Refreshed => return "refreshed",
+            StatusField::UserId => return "user_id",
+            StatusField::Scopes => return "scopes",
+        }
+    }
+}
+
+// Runs inside SessionMiddleware, so the value is saved together with the change that extends the TTL
+pub(crate) async fn track_session_expiry(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actixError> {
+    let response = next.call(req).await?;
+
+    // The store extends the TTL whenever the session is written, never when it is only read
+    let session = response.request().get_session();
+    let written = matches!(session.status(), SessionStatus::Changed | SessionStatus::Renewed);
+    if written && matches!(session.get::<String>("user_id"), Ok(Some(_))) {
+        if let Err(error) = store_session_expiry(&session) {
+            error!("[{}] failed to store session_expiry: {}", request_id(), error);
+        }
+    }
+    return Ok(response);
+}
+
+fn store_session_expiry(session: &Session) -> Result<(), String> {
+    let expires_at = (Utc::now() + chronoDuration::days(SESSION_TTL_DAYS)).timestamp();
+    match session.insert("session_expiry", expires_at) {
+        Ok(_) => return Ok(()),
+        Err(error) => return Err(format!("{:?}", error)),
+    }
+}
+
+// The scope of a token response, None keeps what was stored before (RFC 6749 section 5.1, scope is optional)
+pub(crate) fn store_scopes(session: &Session, scopes: Option<Vec<String>>) -> Result<(), String> {
+    let scopes = match scopes {
+        Some(scopes) => scopes,
+        None => return Ok(()),
+    };
+    match session.insert("scopes", scopes) {
+        Ok(_) => return Ok(()),
+        Err(error) => return Err(format!("{:?}", error)),
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -13,9 +13,13 @@
 //!
 //! ---------------------------------------- //
 
+use crate::tools::responsemode::ResponseMode;
+use crate::tools::claims::UsernameClaim;
+use crate::tools::sessioninfo::StatusField;
 use better_logger::{LoggerSettings, NetworkFormat};
 use std::fs::read_to_string;
 use serde::Deserialize;
+use url::Url;
 
 pub(crate) const LOGGING_CONFIG_PATH: &str = "logging-config.toml";
 pub(crate) const MAIN_CONFIG_PATH: &str = "main-config.toml";
@@ -87,7 +91,7 @@ pub(crate) struct ReadConfiguration {
     pub(crate) issuer_url: String,
     pub(crate) logout_url: String,
     pub(crate) client: String,
-    pub(crate) client_secret: String,
+    pub(crate) client_secret: Option<String>, // Checked against token_endpoint_auth_method by ClientAuthentication
 
     pub(crate) listen_address: Option<String>,
     pub(crate) listen_port: Option<u16>,
@@ -106,6 +110,54 @@ pub(crate) struct ReadConfiguration {
     pub(crate) user_details_fail_when_not_authenticated: Option<bool>,
     pub(crate) default_username: Option<String>,
     pub(crate) default_user_id: Option<String>,
+    pub(crate) provider_refresh_interval_mins: Option<u64>,
+    pub(crate) provider_refresh_min_interval_secs: Option<u64>,
+    pub(crate) startup_retry_max_attempts: Option<u32>,
+    pub(crate) startup_retry_initial_delay_secs: Option<u64>,
+    pub(crate) startup_retry_max_delay_secs: Option<u64>,
+    pub(crate) lazy_discovery: Option<bool>,
+    pub(crate) shutdown_timeout_secs: Option<u64>,
+    pub(crate) shutdown_readiness_delay_secs: Option<u64>,
+    pub(crate) tls_cert_path: Option<String>,
+    pub(crate) tls_key_path: Option<String>,
+    pub(crate) tls_reload_interval_secs: Option<u64>,
+    pub(crate) http_redirect_port: Option<u16>,
+    pub(crate) token_endpoint_auth_method: Option<String>,
+    pub(crate) client_assertion_key_path: Option<String>,
+    pub(crate) client_assertion_key_id: Option<String>,
+    pub(crate) client_assertion_alg: Option<String>,
+    pub(crate) provider_client_cert_path: Option<String>,
+    pub(crate) provider_client_key_path: Option<String>,
+    pub(crate) provider_ca_bundle_path: Option<String>,
+    pub(crate) provider_connect_timeout_secs: Option<u64>,
+    pub(crate) provider_request_timeout_secs: Option<u64>,
+    pub(crate) provider_pool_max_idle_per_host: Option<usize>,
+    pub(crate) provider_pool_idle_timeout_secs: Option<u64>,
+    pub(crate) provider_proxy_url: Option<String>,
+    pub(crate) provider_no_proxy: Option<String>,
+    pub(crate) provider_retry_max_attempts: Option<u32>,
+    pub(crate) provider_retry_initial_delay_ms: Option<u64>,
+    pub(crate) pushed_authorization_requests: Option<bool>,
+    pub(crate) request_object_key_path: Option<String>,
+    pub(crate) request_object_key_id: Option<String>,
+    pub(crate) request_object_alg: Option<String>,
+    pub(crate) dpop: Option<bool>,
+    pub(crate) response_mode: Option<ResponseMode>,
+    pub(crate) allow_missing_refresh_token: Option<bool>,
+    pub(crate) username_claims: Option<Vec<UsernameClaim>>,
+    pub(crate) silent_reauth: Option<bool>,
+    pub(crate) error_redirect_url: Option<String>,
+    pub(crate) audit_log_path: Option<String>,
+    pub(crate) audit_endpoint_url: Option<String>,
+    pub(crate) otlp_traces_endpoint: Option<String>,
+    pub(crate) otlp_service_name: Option<String>,
+    pub(crate) sessionstatus_fields: Option<Vec<StatusField>>,
+    pub(crate) session_events: Option<bool>,
+    pub(crate) token_route: Option<bool>,
+    pub(crate) token_route_allowed_origins: Option<Vec<String>>,
+    pub(crate) token_route_allowed_audiences: Option<Vec<String>>,
+    pub(crate) token_route_allowed_scopes: Option<Vec<String>>,
+    pub(crate) token_exchange_audiences: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -118,7 +170,7 @@ pub(crate) struct MainConfiguration {
     pub(crate) issuer_url: String,
     pub(crate) logout_url: String,
     pub(crate) client: String,
-    pub(crate) client_secret: String,
+    pub(crate) client_secret: Option<String>, // Checked against token_endpoint_auth_method by ClientAuthentication
 
     pub(crate) listen_address: String,
     pub(crate) listen_port: u16,
@@ -137,6 +189,54 @@ pub(crate) struct MainConfiguration {
     pub(crate) user_details_fail_when_not_authenticated: bool,
     pub(crate) default_username: String,
     pub(crate) default_user_id: String,
+    pub(crate) provider_refresh_interval_mins: u64,
+    pub(crate) provider_refresh_min_interval_secs: u64,
+    pub(crate) startup_retry_max_attempts: u32,
+    pub(crate) startup_retry_initial_delay_secs: u64,
+    pub(crate) startup_retry_max_delay_secs: u64,
+    pub(crate) lazy_discovery: bool,
+    pub(crate) shutdown_timeout_secs: u64,
+    pub(crate) shutdown_readiness_delay_secs: u64,
+    pub(crate) tls_cert_path: Option<String>,
+    pub(crate) tls_key_path: Option<String>,
+    pub(crate) tls_reload_interval_secs: u64,
+    pub(crate) http_redirect_port: Option<u16>,
+    pub(crate) token_endpoint_auth_method: String,
+    pub(crate) client_assertion_key_path: Option<String>,
+    pub(crate) client_assertion_key_id: Option<String>,
+    pub(crate) client_assertion_alg: Option<String>,
+    pub(crate) provider_client_cert_path: Option<String>,
+    pub(crate) provider_client_key_path: Option<String>,
+    pub(crate) provider_ca_bundle_path: Option<String>,
+    pub(crate) provider_connect_timeout_secs: u64,
+    pub(crate) provider_request_timeout_secs: u64,
+    pub(crate) provider_pool_max_idle_per_host: usize,
+    pub(crate) provider_pool_idle_timeout_secs: u64,
+    pub(crate) provider_proxy_url: Option<String>,
+    pub(crate) provider_no_proxy: Option<String>,
+    pub(crate) provider_retry_max_attempts: u32,
+    pub(crate) provider_retry_initial_delay_ms: u64,
+    pub(crate) pushed_authorization_requests: bool,
+    pub(crate) request_object_key_path: Option<String>,
+    pub(crate) request_object_key_id: Option<String>,
+    pub(crate) request_object_alg: String,
+    pub(crate) dpop: bool,
+    pub(crate) response_mode: ResponseMode,
+    pub(crate) allow_missing_refresh_token: bool,
+    pub(crate) username_claims: Vec<UsernameClaim>,
+    pub(crate) silent_reauth: bool,
+    pub(crate) error_redirect_url: String,
+    pub(crate) audit_log_path: Option<String>,
+    pub(crate) audit_endpoint_url: Option<String>,
+    pub(crate) otlp_traces_endpoint: Option<String>,
+    pub(crate) otlp_service_name: String,
+    pub(crate) sessionstatus_fields: Vec<StatusField>,
+    pub(crate) session_events: bool,
+    pub(crate) token_route: bool,
+    pub(crate) token_route_allowed_origins: Vec<String>,
+    pub(crate) token_route_allowed_audiences: Vec<String>,
+    pub(crate) token_route_allowed_scopes: Vec<String>,
+    pub(crate) token_exchange_audiences: Vec<String>,
 }
 
 impl MainConfiguration {
@@ -219,6 +319,147 @@ impl MainConfiguration {
             Some(id) => id,
             None => "0".to_string(),
         };
+        let provider_refresh_interval_mins = match config.provider_refresh_interval_mins {
+            Some(interval) => interval,
+            None => 60,
+        };
+        let provider_refresh_min_interval_secs = match config.provider_refresh_min_interval_secs {
+            Some(interval) => interval,
+            None => 60,
+        };
+        let startup_retry_max_attempts = match config.startup_retry_max_attempts {
+            Some(attempts) => attempts,
+            None => 10,
+        };
+        let startup_retry_initial_delay_secs = match config.startup_retry_initial_delay_secs {
+            Some(delay) => delay,
+            None => 1,
+        };
+        let startup_retry_max_delay_secs = match config.startup_retry_max_delay_secs {
+            Some(delay) => delay,
+            None => 30,
+        };
+        let lazy_discovery = match config.lazy_discovery {
+            Some(lazy) => lazy,
+            None => false,
+        };
+        let shutdown_timeout_secs = match config.shutdown_timeout_secs {
+            Some(timeout) => timeout,
+            None => 30,
+        };
+        let shutdown_readiness_delay_secs = match config.shutdown_readiness_delay_secs {
+            Some(delay) => delay,
+            None => 0,
+        };
+        let tls_reload_interval_secs = match config.tls_reload_interval_secs {
+            Some(interval) => interval,
+            None => 60,
+        };
+        let token_endpoint_auth_method = match config.token_endpoint_auth_method {
+            Some(method) => method,
+            None => "client_secret_basic".to_string(),
+        };
+        let provider_connect_timeout_secs = match config.provider_connect_timeout_secs {
+            Some(timeout) => timeout,
+            None => 5,
+        };
+        let provider_request_timeout_secs = match config.provider_request_timeout_secs {
+            Some(timeout) => timeout,
+            None => 10,
+        };
+        let provider_pool_max_idle_per_host = match config.provider_pool_max_idle_per_host {
+            Some(idle) => idle,
+            None => 32,
+        };
+        let provider_pool_idle_timeout_secs = match config.provider_pool_idle_timeout_secs {
+            Some(timeout) => timeout,
+            None => 90,
+        };
+        let provider_retry_max_attempts = match config.provider_retry_max_attempts {
+            Some(attempts) => attempts,
+            None => 3,
+        };
+        let provider_retry_initial_delay_ms = match config.provider_retry_initial_delay_ms {
+            Some(delay) => delay,
+            None => 200,
+        };
+        let pushed_authorization_requests = match config.pushed_authorization_requests {
+            Some(par) => par,
+            None => false,
+        };
+        let request_object_alg = match config.request_object_alg {
+            Some(alg) => alg,
+            None => "RS256".to_string(),
+        };
+        let dpop = match config.dpop {
+            Some(dpop) => dpop,
+            None => false,
+        };
+        let response_mode = match config.response_mode {
+            Some(mode) => mode,
+            None => ResponseMode::Query,
+        };
+        let allow_missing_refresh_token = match config.allow_missing_refresh_token {
+            Some(allow) => allow,
+            None => false,
+        };
+        let username_claims = match config.username_claims {
+            Some(chain) => {
+                if chain.is_empty() {
+                    return Err("username_claims must name at least one claim".to_string());
+                }
+                chain
+            }
+            None => vec![UsernameClaim::PreferredUsername],
+        };
+        let silent_reauth = match config.silent_reauth {
+            Some(silent) => silent,
+            None => false,
+        };
+        let error_redirect_url = match config.error_redirect_url {
+            Some(url) => url,
+            None => config.requesting_client_url.clone(),
+        };
+        // Failures append ?error=<code>&error_id=<id>, an unparsable URL would lose them
+        if let Err(error) = Url::parse(&error_redirect_url) {
+            return Err(format!("error_redirect_url {:?} is not a valid absolute URL: {:?}", error_redirect_url, error));
+        }
+        let otlp_service_name = match config.otlp_service_name {
+            Some(name) => name,
+            None => "gobsg".to_string(),
+        };
+        let sessionstatus_fields = match config.sessionstatus_fields {
+            Some(fields) => fields,
+            None => Vec::new(),
+        };
+        let session_events = match config.session_events {
+            Some(enabled) => enabled,
+            None => false,
+        };
+        let token_route = match config.token_route {
+            Some(enabled) => enabled,
+            None => false,
+        };
+        let token_route_allowed_origins = match config.token_route_allowed_origins {
+            Some(origins) => origins,
+            None => vec![config.requesting_client_url.clone()],
+        };
+        let token_route_allowed_audiences = match config.token_route_allowed_audiences {
+            Some(audiences) => audiences,
+            None => Vec::new(),
+        };
+        let token_route_allowed_scopes = match config.token_route_allowed_scopes {
+            Some(scopes) => scopes,
+            None => Vec::new(),
+        };
+        let token_exchange_audiences = match config.token_exchange_audiences {
+            Some(audiences) => audiences,
+            None => Vec::new(),
+        };
+        // Without an allowlist any access token would be handed out
+        if token_route && token_route_allowed_audiences.is_empty() && token_route_allowed_scopes.is_empty() && token_exchange_audiences.is_empty() {
+            return Err("token_route requires token_route_allowed_audiences, token_route_allowed_scopes or token_exchange_audiences".to_string());
+        }
 
         return Ok(MainConfiguration {
             this_server_url: config.this_server_url,
@@ -229,7 +470,7 @@ impl MainConfiguration {
             issuer_url: config.issuer_url,
             logout_url: config.logout_url,
             client: config.client,
-            client_secret: config.client_secret,
+            client_secret: config.client_secret, // None = private_key_jwt or a TLS method
 
             listen_address: listen_address,
             listen_port: listen_port,
@@ -248,6 +489,54 @@ impl MainConfiguration {
             user_details_fail_when_not_authenticated: user_details_fail_when_not_authenticated,
             default_username: default_username,
             default_user_id: default_user_id,
+            provider_refresh_interval_mins: provider_refresh_interval_mins,
+            provider_refresh_min_interval_secs: provider_refresh_min_interval_secs,
+            startup_retry_max_attempts: startup_retry_max_attempts,
+            startup_retry_initial_delay_secs: startup_retry_initial_delay_secs,
+            startup_retry_max_delay_secs: startup_retry_max_delay_secs,
+            lazy_discovery: lazy_discovery,
+            shutdown_timeout_secs: shutdown_timeout_secs,
+            shutdown_readiness_delay_secs: shutdown_readiness_delay_secs,
+            tls_cert_path: config.tls_cert_path, // None = plain HTTP
+            tls_key_path: config.tls_key_path,
+            tls_reload_interval_secs: tls_reload_interval_secs,
+            http_redirect_port: config.http_redirect_port, // None = no redirect listener
+            token_endpoint_auth_method: token_endpoint_auth_method,
+            client_assertion_key_path: config.client_assertion_key_path, // Only for private_key_jwt
+            client_assertion_key_id: config.client_assertion_key_id,
+            client_assertion_alg: config.client_assertion_alg, // None = RS256 for private_key_jwt, HS256 for client_secret_jwt
+            provider_client_cert_path: config.provider_client_cert_path, // None = no mutual TLS
+            provider_client_key_path: config.provider_client_key_path,
+            provider_ca_bundle_path: config.provider_ca_bundle_path, // None = only the built-in roots
+            provider_connect_timeout_secs: provider_connect_timeout_secs,
+            provider_request_timeout_secs: provider_request_timeout_secs,
+            provider_pool_max_idle_per_host: provider_pool_max_idle_per_host,
+            provider_pool_idle_timeout_secs: provider_pool_idle_timeout_secs,
+            provider_proxy_url: config.provider_proxy_url, // None = direct connections
+            provider_no_proxy: config.provider_no_proxy,
+            provider_retry_max_attempts: provider_retry_max_attempts,
+            provider_retry_initial_delay_ms: provider_retry_initial_delay_ms,
+            pushed_authorization_requests: pushed_authorization_requests,
+            request_object_key_path: config.request_object_key_path, // None = no request objects
+            request_object_key_id: config.request_object_key_id,
+            request_object_alg: request_object_alg,
+            dpop: dpop,
+            response_mode: response_mode,
+            allow_missing_refresh_token: allow_missing_refresh_token,
+            username_claims: username_claims,
+            silent_reauth: silent_reauth,
+            error_redirect_url: error_redirect_url,
+            audit_log_path: config.audit_log_path, // None = no audit file
+            audit_endpoint_url: config.audit_endpoint_url, // None = no audit endpoint
+            otlp_traces_endpoint: config.otlp_traces_endpoint, // None = no tracing
+            otlp_service_name: otlp_service_name,
+            sessionstatus_fields: sessionstatus_fields,
+            session_events: session_events,
+            token_route: token_route,
+            token_route_allowed_origins: token_route_allowed_origins,
+            token_route_allowed_audiences: token_route_allowed_audiences,
+            token_route_allowed_scopes: token_route_allowed_scopes,
+            token_exchange_audiences: token_exchange_audiences,
         });
     }
 }
@@ -0,0 +1,50 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use better_logger::logger::warn;
+use std::future::Future;
+use tokio::time::{sleep, Duration as tokioDuration};
+
+// Retry a startup step with exponential backoff, dependencies (provider, Redis) may start after GOBSG
+// startup_retry_max_attempts = 0 retries forever
+pub(crate) async fn with_backoff<T, F, Fut>(config_settings: &MainConfiguration, what: &str, mut attempt: F) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    let max_attempts = config_settings.startup_retry_max_attempts;
+    let max_delay = config_settings.startup_retry_max_delay_secs.max(1);
+    let mut delay = config_settings.startup_retry_initial_delay_secs.clamp(1, max_delay);
+    let mut attempt_number: u32 = 1;
+
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) => {
+                if max_attempts != 0 && attempt_number >= max_attempts {
+                    return Err(format!("{} failed after {} attempts: {}", what, attempt_number, error));
+                }
+                warn!("(startup) waiting for {}: attempt {} failed, retrying in {}s: {}", what, attempt_number, delay, error);
+            }
+        }
+
+        sleep(tokioDuration::from_secs(delay)).await;
+        delay = (delay * 2).min(max_delay);
+        attempt_number += 1;
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
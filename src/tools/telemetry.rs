@@ -0,0 +1,181 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::settings::MainConfiguration;
+use crate::tools::requestid::current_request_id;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use actix_web::Error as actixError;
+use actix_web::body::MessageBody;
+use actix_web::cookie::time::Duration as cookieTimeDuration;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap as actixHeaderMap;
+use actix_web::middleware::Next;
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use openidconnect::http::HeaderMap;
+use openidconnect::http::header::{HeaderName, HeaderValue};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry::context::FutureExt;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+
+const TRACER_NAME: &str = "gobsg";
+
+// OTLP over HTTP (protobuf), for example http://localhost:4318/v1/traces of a local collector
+// Without otlp_traces_endpoint the global tracer stays a no-op, spans cost nothing and no traceparent is sent
+pub(crate) fn init_telemetry(config_settings: &MainConfiguration) -> Result<Option<SdkTracerProvider>, String> {
+    let endpoint = match &config_settings.otlp_traces_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    let exporter = match SpanExporter::builder().with_http().with_endpoint(endpoint.as_str()).build() {
+        Ok(exporter) => exporter,
+        Err(error) => return Err(format!("otlp_traces_endpoint {:?}: {:?}", endpoint, error)),
+    };
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(config_settings.otlp_service_name.clone()).build())
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new()); // W3C traceparent and tracestate
+    global::set_tracer_provider(provider.clone());
+    return Ok(Some(provider));
+}
+
+// One server span per request, a traceparent from the caller makes it part of the caller's trace
+pub(crate) async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actixError> {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&RequestHeaders(req.headers())));
+    let route = match req.match_pattern() {
+        Some(pattern) => pattern,
+        None => "unmatched".to_string(), // Not the path, unknown paths would each create a span name
+    };
+
+    let tracer = global::tracer(TRACER_NAME);
+    let mut attributes = vec![
+        KeyValue::new("http.request.method", req.method().to_string()),
+        KeyValue::new("http.route", route.clone()),
+    ];
+    if let Some(id) = current_request_id() {
+        attributes.push(KeyValue::new("request_id", id));
+    }
+    let span = tracer.span_builder(format!("{} {}", req.method(), route))
+        .with_kind(SpanKind::Server)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &parent);
+    let context = parent.with_span(span);
+
+    let result = next.call(req).with_context(context.clone()).await;
+    let span = context.span();
+    match &result {
+        Ok(response) => {
+            span.set_attribute(KeyValue::new("http.response.status_code", response.status().as_u16() as i64));
+            if response.status().is_server_error() {
+                span.set_status(Status::error(response.status().to_string()));
+            }
+        }
+        Err(error) => span.set_status(Status::error(error.to_string())),
+    }
+    span.end();
+    return result;
+}
+
+// Runs the future in a child span of the current one, an Err marks the span as failed
+pub(crate) async fn traced<T, E: Debug>(name: &'static str, kind: SpanKind, future: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let tracer = global::tracer(TRACER_NAME);
+    let span = tracer.span_builder(name).with_kind(kind).start(&tracer);
+    let context = Context::current_with_span(span);
+
+    let result = future.with_context(context.clone()).await;
+    if let Err(error) = &result {
+        context.span().set_status(Status::error(format!("{:?}", error)));
+    }
+    context.span().end();
+    return result;
+}
+
+// Adds traceparent (and tracestate) of the current span to a request GOBSG sends
+pub(crate) fn inject_trace_context(headers: &mut HeaderMap) {
+    global::get_text_map_propagator(|propagator| propagator.inject(&mut RequestHeadersMut(headers)));
+}
+
+struct RequestHeaders<'a>(&'a actixHeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.0.get(key) {
+            Some(value) => return value.to_str().ok(),
+            None => return None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        return self.0.keys().map(|name| name.as_str()).collect();
+    }
+}
+
+struct RequestHeadersMut<'a>(&'a mut HeaderMap);
+
+impl Injector for RequestHeadersMut<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+// Wraps the Redis session store, so session load and save show up in the trace of the request
+#[derive(Clone)]
+pub(crate) struct TracedSessionStore<S: SessionStore> {
+    inner: S,
+}
+
+impl<S: SessionStore> TracedSessionStore<S> {
+    pub(crate) fn new(inner: S) -> TracedSessionStore<S> {
+        return TracedSessionStore { inner: inner };
+    }
+}
+
+impl<S: SessionStore> SessionStore for TracedSessionStore<S> {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        return traced("session load", SpanKind::Client, self.inner.load(session_key)).await;
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &cookieTimeDuration) -> Result<SessionKey, SaveError> {
+        return traced("session save", SpanKind::Client, self.inner.save(session_state, ttl)).await;
+    }
+
+    async fn update(&self, session_key: SessionKey, session_state: HashMap<String, String>, ttl: &cookieTimeDuration) -> Result<SessionKey, UpdateError> {
+        return traced("session update", SpanKind::Client, self.inner.update(session_key, session_state, ttl)).await;
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &cookieTimeDuration) -> Result<(), anyhow::Error> {
+        return traced("session update_ttl", SpanKind::Client, self.inner.update_ttl(session_key, ttl)).await;
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        return traced("session delete", SpanKind::Client, self.inner.delete(session_key)).await;
+    }
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
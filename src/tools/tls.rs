@@ -0,0 +1,157 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use better_logger::logger::{debugx, error};
+use std::fs::metadata;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use rustls::ServerConfig;
+use rustls::crypto::ring::default_provider;
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, MissedTickBehavior, Duration as tokioDuration};
+
+// Serves the most recently loaded certificate, the reload task swaps it when the files change on disk
+#[derive(Debug)]
+pub(crate) struct ReloadingCertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+    modified: RwLock<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl ReloadingCertResolver {
+    pub(crate) fn new(cert_path: &str, key_path: &str) -> Result<ReloadingCertResolver, String> {
+        let modified = modified_times(cert_path, key_path);
+        let certified_key = load_certified_key(cert_path, key_path)?;
+
+        return Ok(ReloadingCertResolver {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            current: RwLock::new(Arc::new(certified_key)),
+            modified: RwLock::new(modified),
+        });
+    }
+
+    // Reload if either file changed, a broken file or a mismatched pair keeps the old certificate in use
+    fn reload_if_changed(&self) {
+        let modified = modified_times(&self.cert_path, &self.key_path);
+        let previous = match self.modified.read() {
+            Ok(guard) => *guard,
+            Err(poisoned) => *poisoned.into_inner(),
+        };
+        if modified == previous {
+            return;
+        }
+
+        match load_certified_key(&self.cert_path, &self.key_path) {
+            Ok(certified_key) => {
+                match self.current.write() {
+                    Ok(mut guard) => *guard = Arc::new(certified_key),
+                    Err(poisoned) => *poisoned.into_inner() = Arc::new(certified_key),
+                }
+                match self.modified.write() {
+                    Ok(mut guard) => *guard = modified,
+                    Err(poisoned) => *poisoned.into_inner() = modified,
+                }
+                debugx!("tls certificate reloaded");
+            }
+            Err(error) => error!("(tls) certificate reload failed, keeping the current certificate: {}", error),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        match self.current.read() {
+            Ok(guard) => return Some(guard.clone()),
+            Err(poisoned) => return Some(poisoned.into_inner().clone()),
+        }
+    }
+}
+
+fn modified_times(cert_path: &str, key_path: &str) -> (Option<SystemTime>, Option<SystemTime>) {
+    let cert_modified = metadata(cert_path).and_then(|meta| meta.modified()).ok();
+    let key_modified = metadata(key_path).and_then(|meta| meta.modified()).ok();
+    return (cert_modified, key_modified);
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let certificate_chain = match CertificateDer::pem_file_iter(cert_path) {
+        Ok(iter) => {
+            match iter.collect::<Result<Vec<CertificateDer<'static>>, _>>() {
+                Ok(chain) => chain,
+                Err(error) => return Err(format!("tls_cert_path {:?}: {:?}", cert_path, error)),
+            }
+        }
+        Err(error) => return Err(format!("tls_cert_path {:?}: {:?}", cert_path, error)),
+    };
+    if certificate_chain.is_empty() {
+        return Err(format!("tls_cert_path {:?}: no certificates found", cert_path));
+    }
+
+    let private_key = match PrivateKeyDer::from_pem_file(key_path) {
+        Ok(key) => key,
+        Err(error) => return Err(format!("tls_key_path {:?}: {:?}", key_path, error)),
+    };
+
+    let signing_key = match any_supported_type(&private_key) {
+        Ok(key) => key,
+        Err(error) => return Err(format!("tls_key_path {:?}: {:?}", key_path, error)),
+    };
+
+    // A rotation that writes the two files one after the other can be caught halfway, never serve such a pair
+    let certified_key = CertifiedKey::new(certificate_chain, signing_key);
+    if let Err(error) = certified_key.keys_match() {
+        return Err(format!("tls_cert_path {:?} and tls_key_path {:?} do not belong together: {:?}", cert_path, key_path, error));
+    }
+
+    return Ok(certified_key);
+}
+
+// Build the rustls config and start watching the certificate files
+pub(crate) fn new_server_config(cert_path: &str, key_path: &str, reload_interval_secs: u64) -> Result<ServerConfig, String> {
+    let resolver = Arc::new(ReloadingCertResolver::new(cert_path, key_path)?);
+
+    let mut server_config = match ServerConfig::builder_with_provider(Arc::new(default_provider())).with_safe_default_protocol_versions() {
+        Ok(builder) => builder.with_no_client_auth().with_cert_resolver(resolver.clone()),
+        Err(error) => return Err(format!("{:?}", error)),
+    };
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    if reload_interval_secs > 0 {
+        tokio::spawn(async move {
+            let mut time = interval(tokioDuration::from_secs(reload_interval_secs));
+            time.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            time.tick().await;
+            loop {
+                time.tick().await;
+                // Reads the files with std::fs, kept off the async workers
+                let reload_resolver = resolver.clone();
+                if let Err(error) = spawn_blocking(move || reload_resolver.reload_if_changed()).await {
+                    error!("(tls) certificate reload task failed: {:?}", error);
+                }
+            }
+        });
+    }
+
+    return Ok(server_config);
+}
+
+////////// END OF FILE //////////
\ No newline at end of file
@@ -0,0 +1,159 @@
+//!/////// START OF FILE //////////
+//! GOBSG
+//!
+//! Copyright (c) 2026 Gistyr LLC
+//!
+//! Licensed under the PolyForm Small Business License 1.0.0
+//! See LICENSES/LICENSE-POLYFORM-SMALL-BUSINESS.md or https://polyformproject.org/licenses/small-business/1.0.0
+//!
+//! Required Notice: Copyright Gistyr LLC (https://gistyr.dev)
+//!
+//! For full licenses see:
+//! LICENSES/
+//!
+//! ---------------------------------------- //
+
+use crate::tools::clientauth::ClientAuthentication;
+use crate::tools::providerhttp::ProviderHttp;
+use crate::tools::telemetry::traced;
+use std::collections::HashMap;
+use actix_session::Session;
+use opentelemetry::trace::SpanKind;
+use serde::{Deserialize, Serialize};
+use chrono::Utc;
+
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+
+// Exchanged tokens by audience, kept in the session until they expire, tokens without a known expiry are not kept
+pub(crate) const EXCHANGED_TOKENS_SESSION: &str = "exchanged_tokens";
+
+// RFC 8693 section 2.2.1
+#[derive(Deserialize)]
+struct TokenExchangeResponse {
+    access_token: String,
+    issued_token_type: String, // Required, unlike in a plain token response
+    token_type: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ExchangedToken {
+    pub(crate) access_token: String,
+    pub(crate) issued_token_type: String,
+    pub(crate) token_type: String,
+    pub(crate) expires_at: Option<i64>, // None: the provider sent no expires_in, the token is used once and not cached
+}
+
+#[derive(Debug)]
+pub(crate) enum TokenExchangeError {
+    Transient(String), // The provider could not be reached or answered with a server error, the session is fine
+    Rejected(String), // The provider refused the exchange (policy, audience, expired subject token)
+}
+
+// The cached token for the audience, or a new one from the token endpoint (RFC 8693)
+// A cached token is reused until early_refresh_skew_secs before its expiry, the same margin as the session's own token
+pub(crate) async fn exchanged_token(
+    session: &Session,
+    provider_http: &ProviderHttp,
+    client_auth: &ClientAuthentication,
+    token_endpoint: &str,
+    subject_token: &str,
+    audience: &str,
+    early_refresh_skew_secs: i64,
+) -> Result<ExchangedToken, TokenExchangeError> {
+    let mut cache = match session.get::<HashMap<String, ExchangedToken>>(EXCHANGED_TOKENS_SESSION) {
+        Ok(Some(cache)) => cache,
+        _ => HashMap::new(),
+    };
+    let now = Utc::now().timestamp();
+    cache.retain(|_, token| {
+        match token.expires_at {
+            Some(expires_at) => now < expires_at - early_refresh_skew_secs,
+            None => false,
+        }
+    });
+    if let Some(token) = cache.get(audience) {
+        return Ok(token.clone());
+    }
+
+    let token = traced("token exchange (RFC 8693)", SpanKind::Client, exchange(provider_http, client_auth, token_endpoint, subject_token, audience)).await?;
+    if token.expires_at.is_none() {
+        return Ok(token);
+    }
+    cache.insert(audience.to_string(), token.clone());
+    if let Err(error) = session.insert(EXCHANGED_TOKENS_SESSION, cache) {
+        return Err(TokenExchangeError::Transient(format!("failed to cache the exchanged token: {:?}", error)));
+    }
+    return Ok(token);
+}
+
+async fn exchange(
+    provider_http: &ProviderHttp,
+    client_auth: &ClientAuthentication,
+    token_endpoint: &str,
+    subject_token: &str,
+    audience: &str,
+) -> Result<ExchangedToken, TokenExchangeError> {
+    let mut form: Vec<(String, String)> = vec![
+        ("grant_type".to_string(), TOKEN_EXCHANGE_GRANT_TYPE.to_string()),
+        ("subject_token".to_string(), subject_token.to_string()),
+        ("subject_token_type".to_string(), ACCESS_TOKEN_TYPE.to_string()),
+        ("requested_token_type".to_string(), ACCESS_TOKEN_TYPE.to_string()),
+        ("audience".to_string(), audience.to_string()),
+    ];
+
+    // Same client authentication as every other request to the token endpoint
+    let credentials = match client_auth.form_credentials(token_endpoint) {
+        Ok(credentials) => credentials,
+        Err(error) => return Err(TokenExchangeError::Rejected(error)),
+    };
+    for (name, value) in credentials.params {
+        form.push((name.to_string(), value));
+    }
+
+    let mut request = provider_http.post(token_endpoint).form(&form);
+    if let Some((client_id, client_secret)) = credentials.basic {
+        request = request.basic_auth(client_id, Some(client_secret));
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(error) => return Err(TokenExchangeError::Transient(format!("token exchange request failed: {:?}", error))),
+    };
+    let status = response.status();
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(error) => return Err(TokenExchangeError::Transient(format!("token exchange response failed: {:?}", error))),
+    };
+    if status.is_server_error() {
+        return Err(TokenExchangeError::Transient(format!("token endpoint returned {}: {}", status, body)));
+    }
+    if !status.is_success() {
+        return Err(TokenExchangeError::Rejected(format!("token endpoint returned {}: {}", status, body)));
+    }
+
+    let exchanged = match serde_json::from_str::<TokenExchangeResponse>(&body) {
+        Ok(exchanged) => exchanged,
+        Err(error) => return Err(TokenExchangeError::Rejected(format!("token exchange response is not valid: {:?}", error))),
+    };
+    // The frontend sends the token as a bearer token, an id_token, a refresh token or a DPoP-bound token would not work
+    if exchanged.issued_token_type != ACCESS_TOKEN_TYPE && exchanged.issued_token_type != JWT_TOKEN_TYPE {
+        return Err(TokenExchangeError::Rejected(format!("token exchange issued an unsupported token type: {}", exchanged.issued_token_type)));
+    }
+    if !exchanged.token_type.eq_ignore_ascii_case("bearer") {
+        return Err(TokenExchangeError::Rejected(format!("token exchange returned a {} token, only Bearer is supported", exchanged.token_type)));
+    }
+    // Without expires_in the expiry is unknown, the token is not cached beyond this request
+    let expires_at = exchanged.expires_in.map(|expires_in| Utc::now().timestamp().saturating_add(i64::try_from(expires_in).unwrap_or(i64::MAX)));
+
+    return Ok(ExchangedToken {
+        access_token: exchanged.access_token,
+        issued_token_type: exchanged.issued_token_type,
+        token_type: exchanged.token_type,
+        expires_at: expires_at,
+    });
+}
+
+////////// END OF FILE //////////
\ No newline at end of file